@@ -6,11 +6,20 @@ import HowToPlay from "./components/HowToPlay.js";
 
 const BACKEND_URL = `${process.env.REACT_APP_SERVER_URL}:${process.env.REACT_APP_SERVER_PORT}/api`;
 
+// The engine the server plays a bot difficulty with
+const botEngine = (difficulty) => (
+    difficulty === 1
+        ? { engine: "random", difficulty: 1 }
+        : { engine: "minimax", difficulty: 2 }
+);
+
 function App() {
     const [serverSanityCheckError, setServerSanityCheckError] = React.useState('');
     const [playerWhiteBot, setPlayerWhiteBot] = React.useState(false);
     const [playerBlackBot, setPlayerBlackBot] = React.useState(false);
     const [playerName, setPlayerName] = React.useState(localStorage.getItem("playerName") || "");
+    const [password, setPassword] = React.useState("");
+    const [token, setToken] = React.useState(localStorage.getItem("token") || "");
     const [gameId, setGameId] = React.useState(localStorage.getItem("gameId") || "");
     const [accordionOpen, setAccordionOpen] = React.useState(false);
     const [loading, setLoading] = React.useState(false);
@@ -32,16 +41,54 @@ function App() {
         sanityCheck();
     }, []);
 
+    // Logs the player in, or registers them if the name isn't taken yet
+    const getSessionToken = async () => {
+        if (token && localStorage.getItem("playerName") === playerName) {
+            return token;
+        }
+
+        if (!password) {
+            alert("Enter your password!");
+            return "";
+        }
+
+        const credentials = {
+            player_name: playerName,
+            password: password,
+        };
+
+        let response;
+        try {
+            response = await axios.post(`${BACKEND_URL}/v1/sessions`, credentials);
+        } catch (err) {
+            if (err.response?.status !== 401) {
+                throw err;
+            }
+
+            try {
+                response = await axios.post(`${BACKEND_URL}/v1/players`, credentials);
+            } catch (registerErr) {
+                if (registerErr.response?.status === 409) {
+                    alert("Wrong password!");
+                    return "";
+                }
+                throw registerErr;
+            }
+        }
+
+        localStorage.setItem("token", response.data.token);
+        setToken(response.data.token);
+        return response.data.token;
+    }
+
     const handleStartGameOnClick = async () => {
         if (!playerName) {
             return;
         }
 
-        if (playerWhiteBot && playerBlackBot) {
-            alert("Cannot create a game without a human player!");
-            return;
-        }
-        
+        // Bots play both sides of an exhibition, each with its own difficulty
+        const isExhibition = playerWhiteBot && playerBlackBot;
+
         localStorage.setItem("playerName", playerName);
         
         let difficulty;
@@ -55,22 +102,31 @@ function App() {
         }
 
         try {
+            const sessionToken = await getSessionToken();
+            if (!sessionToken) {
+                return;
+            }
+
             let response = await axios({
                 method: "POST",
-                url: `${BACKEND_URL}/new_game`, 
+                url: `${BACKEND_URL}/v1/games`, 
                 headers: {
                     'Accept': 'application/json',
-                    'Content-Type': 'application/json;charset=UTF-8'
+                    'Content-Type': 'application/json;charset=UTF-8',
+                    'Authorization': `Bearer ${sessionToken}`,
                 },
                 data: {
-                    player_name: playerName,
                     bot_white: !!playerWhiteBot,
                     bot_black: !!playerBlackBot,
                     bot_difficulty: difficulty,
+                    ...(isExhibition && {
+                        white_engine: botEngine(playerWhiteBot),
+                        black_engine: botEngine(playerBlackBot),
+                    }),
                 },
             });
 
-            setGameId(response.data);
+            setGameId(response.data.game_id);
         } catch (err) {
             console.error(err);
         }
@@ -92,6 +148,7 @@ function App() {
             <div>
                 <Board
                     playerName={playerName}
+                    token={token}
                     gameId={gameId}
                     setGameId={setGameId}
                     setLoading={setLoading}
@@ -145,6 +202,16 @@ function App() {
                         onChange={handlePlayerNameInputOnChange}
                     />
                 </div>
+                <div className="col-span-2">
+                    <label htmlFor="password" className="block mb-2 text-sm font-medium text-white">Password</label>
+                    <input 
+                        type="password" 
+                        id="password" 
+                        className="bg-amber-900 border border-amber-500 text-white text-sm rounded-lg focus:ring-amber-500 focus:border-amber-500 block w-full p-2.5"
+                        value={password}
+                        onChange={(e) => setPassword(e.target.value)}
+                    />
+                </div>
                 <button 
                     className="bg-slate-300 hover:bg-slate-100 text-black font-bold py-2 px-4 rounded"
                     onClick={() => {
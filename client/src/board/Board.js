@@ -14,12 +14,15 @@ import {boardFromCHFEN} from '../util/boardFromCHFEN';
 
 const BACKEND_URL = `${process.env.REACT_APP_SERVER_URL}:${process.env.REACT_APP_SERVER_PORT}/api`;
 
-const Board = ({playerName, gameId, setGameId, setLoading}) => {
+const Board = ({playerName, token, gameId, setGameId, setLoading}) => {
     const [board, setBoard] = React.useState([[]]);
     const [selectedTiles, setSelectedTiles] = React.useState([]);
     const [selected, setSelected] = React.useState("");
     const [currentPlayer, setCurrentPlayer] = React.useState("");
     const [winner, setWinner] = React.useState("x");
+    const [botThinking, setBotThinking] = React.useState(false);
+    const [drawOfferedBy, setDrawOfferedBy] = React.useState(null);
+    const [ply, setPly] = React.useState(0);
 
     const tile_to_img = (tile, x, y) => {
         if (selectedTiles.includes(`${x}, ${y}`)) {
@@ -68,40 +71,85 @@ const Board = ({playerName, gameId, setGameId, setLoading}) => {
         }
     }
 
+    const get_board = async () => {
+        let response = await axios({
+            method: "GET",
+            url: `${BACKEND_URL}/v1/games/${gameId}`,
+            headers: {
+                'Accept': 'application/json',
+                'Authorization': `Bearer ${token}`,
+            },
+        });
+
+        if (response.status !== 200) {
+            setGameId("");
+            return;
+        }
+
+        if (response.data.fen[response.data.fen.length - 1] === 'b') {
+            setCurrentPlayer('B');
+        } else {
+            setCurrentPlayer('W');
+        }
+
+        setBoard(boardFromCHFEN(response.data.fen));
+        setWinner(response.data.winner);
+        setBotThinking(response.data.bot_thinking);
+        setDrawOfferedBy(response.data.draw_offered_by);
+        setPly(response.data.ply);
+    };
+
+    // Resigning and draw offers all answer with the new state of the board
+    const gameAction = async (endpoint, data) => {
+        let response = await axios({
+            method: "POST",
+            url: `${BACKEND_URL}/v1/games/${gameId}/${endpoint}`,
+            headers: {
+                'Accept': 'application/json',
+                'Content-Type': 'application/json;charset=UTF-8',
+                'Authorization': `Bearer ${token}`,
+            },
+            data: data,
+        });
+
+        if (response.status !== 200) {
+            return;
+        }
+
+        setWinner(response.data.winner);
+        setDrawOfferedBy(response.data.draw_offered_by);
+    };
+
     React.useEffect(() => {
-        const get_board = async () => {
-            let response = await axios({
-                method: "POST",
-                url: `${BACKEND_URL}/get_board`, 
-                headers: {
-                    'Accept': 'application/json',
-                    'Content-Type': 'application/json;charset=UTF-8'
-                },
-                data: {
-                    game_id: gameId,
-                },
-            });
+        get_board();
+    }, []);
 
-            if (response.status !== 200) {
-                setGameId("");
-                return;
-            }
+    // Moves, bot replies and the result of the game are pushed by the server
+    React.useEffect(() => {
+        // WebSockets can't send the Authorization header, the token goes into the query instead
+        const socket = new WebSocket(`${BACKEND_URL.replace(/^http/, 'ws')}/v1/games/${gameId}/live?session_token=${token}`);
 
-            if (response.data.fen[response.data.fen.length - 1] === 'b') {
+        socket.onmessage = (message) => {
+            const update = JSON.parse(message.data);
+
+            if (update.fen[update.fen.length - 1] === 'b') {
                 setCurrentPlayer('B');
             } else {
                 setCurrentPlayer('W');
             }
 
-            setBoard(boardFromCHFEN(response.data.fen));
-            setWinner(response.data.winner);
+            setBoard(boardFromCHFEN(update.fen));
+            setWinner(update.winner);
+            setBotThinking(update.bot_thinking);
+            setDrawOfferedBy(update.draw_offered_by);
+            setPly(update.ply);
         };
 
-        get_board();
-    }, []);
+        return () => socket.close();
+    }, [gameId]);
 
     const handleTileOnClick = async (x,y) => {
-        if (winner !== 'x') {
+        if (winner !== 'x' || botThinking) {
             return;
         }
 
@@ -113,22 +161,33 @@ const Board = ({playerName, gameId, setGameId, setLoading}) => {
 
         if (selectedTiles.includes(`${x}, ${y}`)) {
             setLoading(true);
-            let response = await axios({
-                method: "POST",
-                url: `${BACKEND_URL}/make_move`,
-                headers: {
-                    'Accept': 'application/json',
-                    'Content-Type': 'application/json;charset=UTF-8'
-                },
-                data: {
-                    player_name: playerName,
-                    game_id: gameId,
-                    x_from: selectedX,
-                    y_from: selectedY,
-                    x_to: x,
-                    y_to: y,
-                } 
-            });
+            let response;
+            try {
+                response = await axios({
+                    method: "POST",
+                    url: `${BACKEND_URL}/v1/games/${gameId}/moves`,
+                    headers: {
+                        'Accept': 'application/json',
+                        'Content-Type': 'application/json;charset=UTF-8',
+                        'Authorization': `Bearer ${token}`,
+                    },
+                    data: {
+                        x_from: selectedX,
+                        y_from: selectedY,
+                        x_to: x,
+                        y_to: y,
+                        expected_ply: ply,
+                    } 
+                });
+            } catch (err) {
+                setLoading(false);
+                // The game changed since it was loaded, in another tab for example
+                if (err.response?.data?.code === "stale_move") {
+                    get_board();
+                    return;
+                }
+                throw err;
+            }
             setLoading(false);
 
             if (response.status !== 200) {
@@ -147,6 +206,10 @@ const Board = ({playerName, gameId, setGameId, setLoading}) => {
                 setWinner(response.data.winner);
             }
 
+            setBotThinking(response.data.bot_thinking);
+            setDrawOfferedBy(null);
+            setPly(response.data.ply);
+
             return;
         }
         
@@ -163,26 +226,23 @@ const Board = ({playerName, gameId, setGameId, setLoading}) => {
         }
 
         let response = await axios({
-            method: "POST",
-            url: `${BACKEND_URL}/legal_moves`, 
+            method: "GET",
+            url: `${BACKEND_URL}/v1/games/${gameId}/legal-moves`,
             headers: {
                 'Accept': 'application/json',
-                'Content-Type': 'application/json;charset=UTF-8'
+                'Authorization': `Bearer ${token}`,
             },
-            data: {
-                player_name: playerName,
-                game_id: gameId,
+            params: {
                 x: x,
                 y: y,
             },
         });
 
-        if (typeof response.data !== "string") {
+        if (!Array.isArray(response.data)) {
             return;
         }
-        let filtered_response = response.data.slice(2,-2).split('), (');
         setSelected(`${x}, ${y}`)
-        setSelectedTiles(filtered_response);
+        setSelectedTiles(response.data.map((move) => `${move.to[0]}, ${move.to[1]}`));
     }
 
     return(
@@ -198,6 +258,22 @@ const Board = ({playerName, gameId, setGameId, setLoading}) => {
                     >
                         {"<< Abort game"}
                     </a>
+                    {winner === 'x' &&
+                        <div>
+                            <a
+                                className="cursor-pointer hover:text-slate-700 mr-4"
+                                onClick={() => gameAction("draw-offer", {})}
+                            >
+                                Offer draw
+                            </a>
+                            <a
+                                className="cursor-pointer hover:text-slate-700"
+                                onClick={() => gameAction("resignation", {})}
+                            >
+                                Resign
+                            </a>
+                        </div>
+                    }
                     <span>{playerName}</span>
                 </div>
 
@@ -225,11 +301,21 @@ const Board = ({playerName, gameId, setGameId, setLoading}) => {
                     (
                         <div className="mx-4 my-2 text-center text-white font-bold">
                             Current player: {currentPlayer === 'W' ? <span>White</span> : <span className="text-black">Black</span>}
+                            {botThinking && <span> (bot thinking...)</span>}
+                            {drawOfferedBy &&
+                                <div>
+                                    {drawOfferedBy === 'w' ? 'White' : 'Black'} offered a draw.
+                                    <a className="cursor-pointer underline mx-2" onClick={() => gameAction("draw-offer/response", {accept: true})}>Accept</a>
+                                    <a className="cursor-pointer underline" onClick={() => gameAction("draw-offer/response", {accept: false})}>Decline</a>
+                                </div>
+                            }
                         </div>
                     ) :
                     (
                         <div className="mx-4 my-2 text-center text-white font-bold">
-                            {winner === 'w' ? <span>White won!</span> : <span className="text-black">Black won!</span>}
+                            {winner === 'd' && <span>Draw!</span>}
+                            {winner === 'w' && <span>White won!</span>}
+                            {winner === 'b' && <span className="text-black">Black won!</span>}
                         </div>
                     )
                 }
@@ -0,0 +1,559 @@
+use actix_web::{
+    body::MessageBody,
+    delete, get, post,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    middleware::{from_fn, Next},
+    web, HttpRequest, HttpResponse,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+    IntoParams, Modify, OpenApi,
+};
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::{
+    auth::{Player, require_session},
+    db::DbPool,
+    error::{ApiError, ErrorResponse},
+    game::{LegalMove, notation::Square},
+    listing::{GameListQuery, GamePage, game_page},
+    live::GameHub,
+    moves::RecordedMove,
+    rating::{RatingHistory, load_rating_history},
+    sharing::{LiveViewerQuery, ShareLink, ViewerQuery},
+    stats::{
+        DEFAULT_LEADERBOARD_SIZE, DEFAULT_MIN_GAMES, LeaderboardEntry, LeaderboardSort, PlayerStats,
+        leaderboard, player_stats,
+    },
+    BoardInfoResponse, CredentialsInfo, DrawResponseInfo, GameIdResponse, MoveInfo, NewGameInfo,
+    NewOnlineGameInfo, NewOnlineGameResponse, SessionResponse,
+    answer_draw, create_account, create_game, create_online_game, end_session, join_with_invite,
+    export_record, import_record, list_legal_moves, list_moves, list_share_links, load_board_info, open_live_updates, piece_coords, play_move,
+    propose_draw, resign_game, revoke_share_link, share_game, start_session,
+};
+
+/*
+    Without coordinates or a square, the moves of every piece of the side to move are returned.
+*/
+#[derive(Deserialize, Serialize, IntoParams)]
+struct LegalMovesQuery {
+    // Row of the piece from the top, from 0 to 10
+    x: Option<usize>,
+    // Column of the piece from the left, from 0 to 10
+    y: Option<usize>,
+    // Square of the piece in tafl notation, instead of x and y
+    #[param(value_type = Option<String>, example = "d1")]
+    square: Option<Square>,
+}
+
+#[derive(Deserialize, Serialize, IntoParams)]
+struct LeaderboardQuery {
+    // Defaults to win_rate, rating ranks by the Glicko-2 rating
+    sort: Option<LeaderboardSort>,
+    // Only players with at least this many finished games are ranked, defaults to 1
+    min_games: Option<u32>,
+    // Defaults to 50
+    limit: Option<u32>,
+}
+
+/*
+    OpenAPI document of the /api/v1 routes, generated from the handlers and the request and response structs.
+    The live updates are sent over a WebSocket, which OpenAPI can't describe, only the GameUpdate messages are documented.
+*/
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Krabbi", description = "Play Copenhagen Hnefatafl against bots or other players."),
+    servers((url = "/api/v1")),
+    paths(
+        register, login, logout, player_games, stats, rating, ranking, new_game, new_online_game, join_game,
+        board, make_move, move_history, legal_moves, resign, offer_draw, respond_draw, live_updates,
+        new_share_link, share_links, delete_share_link, export_game, import_game,
+    ),
+    components(schemas(crate::live::GameUpdate, crate::live::GameEvent)),
+    modifiers(&SessionTokenAuth),
+)]
+pub struct ApiDoc;
+
+struct SessionTokenAuth;
+
+impl Modify for SessionTokenAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+
+        components.add_security_scheme(
+            "session_token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+/*
+    Registers the /api/v1 routes. They do the same as the unversioned routes,
+    but name resources in the path, and read them with GET.
+    The OpenAPI document is served at /api/openapi.json, and can be browsed at /api/docs/.
+*/
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        SwaggerUi::new("/api/docs/{_:.*}").url("/api/openapi.json", ApiDoc::openapi())
+    );
+
+    cfg.service(
+        web::scope("/api/v1")
+            .service(register)
+            .service(login)
+            .service(logout)
+            .service(player_games)
+            .service(stats)
+            .service(rating)
+            .service(ranking)
+            .service(new_game)
+            .service(new_online_game)
+            .service(join_game)
+            .service(board)
+            .service(make_move)
+            .service(move_history)
+            .service(legal_moves)
+            .service(resign)
+            .service(offer_draw)
+            .service(respond_draw)
+            .service(new_share_link)
+            .service(share_links)
+            .service(delete_share_link)
+            .service(export_game)
+            .service(import_game)
+            .service(live_updates)
+    );
+}
+
+/*
+    Middleware for the unversioned routes, which are only kept as aliases of the /api/v1 routes.
+*/
+pub async fn mark_deprecated(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::error::Error> {
+    let mut res = next.call(req).await?;
+
+    res.headers_mut().insert(HeaderName::from_static("deprecation"), HeaderValue::from_static("true"));
+
+    Ok(res)
+}
+
+#[utoipa::path(
+    request_body = CredentialsInfo,
+    responses(
+        (status = 201, description = "Player registered and logged in", body = SessionResponse),
+        (status = 400, description = "Empty player name or password", body = ErrorResponse),
+        (status = 409, description = "Player name is taken", body = ErrorResponse),
+    ),
+)]
+#[post("/players")]
+async fn register(credentials: web::Json<CredentialsInfo>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Created().json(create_account(&pool, &credentials)?))
+}
+
+#[utoipa::path(
+    request_body = CredentialsInfo,
+    responses(
+        (status = 201, description = "Logged in", body = SessionResponse),
+        (status = 401, description = "Wrong player name or password", body = ErrorResponse),
+    ),
+)]
+#[post("/sessions")]
+async fn login(credentials: web::Json<CredentialsInfo>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Created().json(start_session(&pool, &credentials)?))
+}
+
+#[utoipa::path(
+    responses(
+        (status = 204, description = "Logged out"),
+        (status = 401, description = "Missing or invalid session token", body = ErrorResponse),
+    ),
+    security(("session_token" = [])),
+)]
+#[delete("/sessions/current", wrap = "from_fn(require_session)")]
+async fn logout(req: HttpRequest, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    end_session(&pool, &req)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/*
+    Players can only list their own games.
+*/
+#[utoipa::path(
+    params(("player_name" = String, Path, description = "Name of the logged in player"), GameListQuery),
+    responses(
+        (status = 200, description = "A page of summaries of the games of the player, newest first by default", body = GamePage),
+        (status = 400, description = "Invalid filter, sort order or page", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid session token", body = ErrorResponse),
+        (status = 403, description = "Not the logged in player", body = ErrorResponse),
+    ),
+    security(("session_token" = [])),
+)]
+#[get("/players/{player_name}/games", wrap = "from_fn(require_session)")]
+async fn player_games(path: web::Path<String>, query: web::Query<GameListQuery>, player: web::ReqData<Player>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    if path.into_inner() != player.name {
+        return Err(ApiError::NotYourAccount);
+    }
+
+    let conn = pool.get()?;
+
+    Ok(HttpResponse::Ok().json(game_page(&conn, &player.name, &query)?))
+}
+
+#[utoipa::path(
+    params(("player_name" = String, Path, description = "Name of the player")),
+    responses(
+        (status = 200, description = "Results of the finished games of the player", body = PlayerStats),
+    ),
+)]
+#[get("/players/{player_name}/stats")]
+async fn stats(path: web::Path<String>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let conn = pool.get()?;
+
+    Ok(HttpResponse::Ok().json(player_stats(&conn, &path.into_inner())?))
+}
+
+/*
+    Bots are rated under their own names, like "Krabbi bot 2".
+*/
+#[utoipa::path(
+    params(("player_name" = String, Path, description = "Name of the player, or of a bot")),
+    responses(
+        (status = 200, description = "Current Glicko-2 rating of the player, and how it changed with every rated game", body = RatingHistory),
+    ),
+)]
+#[get("/players/{player_name}/rating")]
+async fn rating(path: web::Path<String>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let conn = pool.get()?;
+
+    Ok(HttpResponse::Ok().json(load_rating_history(&conn, &path.into_inner())?))
+}
+
+#[utoipa::path(
+    params(LeaderboardQuery),
+    responses(
+        (status = 200, description = "Players ranked by their results", body = Vec<LeaderboardEntry>),
+        (status = 400, description = "Unknown sort order", body = ErrorResponse),
+    ),
+)]
+#[get("/leaderboard")]
+async fn ranking(query: web::Query<LeaderboardQuery>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let conn = pool.get()?;
+
+    Ok(HttpResponse::Ok().json(leaderboard(
+        &conn,
+        query.sort.unwrap_or_default(),
+        query.min_games.unwrap_or(DEFAULT_MIN_GAMES),
+        query.limit.unwrap_or(DEFAULT_LEADERBOARD_SIZE),
+    )?))
+}
+
+#[utoipa::path(
+    request_body = NewGameInfo,
+    responses(
+        (status = 201, description = "Game created", body = GameIdResponse),
+        (status = 400, description = "Invalid bot difficulty, engine or time control, or engines for a game with a human player", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid session token", body = ErrorResponse),
+        (status = 409, description = "Too many exhibitions are being played, by everyone or by the player", body = ErrorResponse),
+    ),
+    security(("session_token" = [])),
+)]
+#[post("/games", wrap = "from_fn(require_session)")]
+async fn new_game(new_game_info: web::Json<NewGameInfo>, player: web::ReqData<Player>, hub: web::Data<GameHub>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let game_id = create_game(&pool, &new_game_info, &player, &hub)?;
+
+    Ok(HttpResponse::Created()
+        .insert_header(("Location", format!("/api/v1/games/{}", game_id)))
+        .json(GameIdResponse { game_id }))
+}
+
+#[utoipa::path(
+    request_body = NewOnlineGameInfo,
+    responses(
+        (status = 201, description = "Game created, the opponent joins with the invite code", body = NewOnlineGameResponse),
+        (status = 400, description = "Invalid color or time control", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid session token", body = ErrorResponse),
+    ),
+    security(("session_token" = [])),
+)]
+#[post("/online-games", wrap = "from_fn(require_session)")]
+async fn new_online_game(new_online_game_info: web::Json<NewOnlineGameInfo>, player: web::ReqData<Player>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let created = create_online_game(&pool, &new_online_game_info, &player)?;
+
+    Ok(HttpResponse::Created()
+        .insert_header(("Location", format!("/api/v1/games/{}", created.game_id)))
+        .json(created))
+}
+
+#[utoipa::path(
+    params(("invite_code" = String, Path, description = "Invite code of an online game")),
+    responses(
+        (status = 200, description = "Joined the game", body = GameIdResponse),
+        (status = 401, description = "Missing or invalid session token", body = ErrorResponse),
+        (status = 404, description = "No game for the invite code", body = ErrorResponse),
+        (status = 409, description = "Game is already full, or the player already plays in it", body = ErrorResponse),
+    ),
+    security(("session_token" = [])),
+)]
+#[post("/invites/{invite_code}/join", wrap = "from_fn(require_session)")]
+async fn join_game(path: web::Path<String>, player: web::ReqData<Player>, hub: web::Data<GameHub>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let game_id = join_with_invite(&pool, &path.into_inner(), &player, &hub).await?;
+
+    Ok(HttpResponse::Ok().json(GameIdResponse { game_id }))
+}
+
+/*
+    Games can be read by their players, and by anyone with a share token of the game.
+*/
+#[utoipa::path(
+    params(("game_id" = String, Path, description = "Id of the game"), ViewerQuery),
+    responses(
+        (status = 200, description = "Current state of the game", body = BoardInfoResponse),
+        (status = 401, description = "Neither a share token nor a session token", body = ErrorResponse),
+        (status = 404, description = "No game found, or not a player of the game, or the share link was revoked", body = ErrorResponse),
+    ),
+    security((), ("session_token" = [])),
+)]
+#[get("/games/{game_id}")]
+async fn board(req: HttpRequest, path: web::Path<String>, viewer: web::Query<ViewerQuery>, hub: web::Data<GameHub>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(load_board_info(&pool, &path.into_inner(), &req, &viewer, &hub).await?))
+}
+
+#[utoipa::path(
+    params(("game_id" = String, Path, description = "Id of the game")),
+    request_body = MoveInfo,
+    responses(
+        (status = 200, description = "State of the game after the move", body = BoardInfoResponse),
+        (status = 400, description = "Illegal move, or not exactly one of notation and coordinates", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid session token", body = ErrorResponse),
+        (status = 403, description = "Not the turn of the player", body = ErrorResponse),
+        (status = 404, description = "No game found", body = ErrorResponse),
+        (status = 409, description = "Game is over, out of time, changed since expected_ply, or waiting for the bot or an opponent", body = ErrorResponse),
+    ),
+    security(("session_token" = [])),
+)]
+#[post("/games/{game_id}/moves", wrap = "from_fn(require_session)")]
+async fn make_move(path: web::Path<String>, move_info: web::Json<MoveInfo>, player: web::ReqData<Player>, hub: web::Data<GameHub>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(play_move(&pool, &path.into_inner(), &move_info, &player, &hub).await?))
+}
+
+#[utoipa::path(
+    params(("game_id" = String, Path, description = "Id of the game"), ViewerQuery),
+    responses(
+        (status = 200, description = "Moves played so far, in order", body = Vec<RecordedMove>),
+        (status = 401, description = "Neither a share token nor a session token", body = ErrorResponse),
+        (status = 404, description = "No game found, or not a player of the game, or the share link was revoked", body = ErrorResponse),
+    ),
+    security((), ("session_token" = [])),
+)]
+#[get("/games/{game_id}/moves")]
+async fn move_history(req: HttpRequest, path: web::Path<String>, viewer: web::Query<ViewerQuery>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(list_moves(&pool, &path.into_inner(), &req, &viewer)?))
+}
+
+#[utoipa::path(
+    params(("game_id" = String, Path, description = "Id of the game"), LegalMovesQuery),
+    responses(
+        (status = 200, description = "Legal moves", body = Vec<LegalMove>),
+        (status = 400, description = "Only one coordinate, both coordinates and a square, or not a piece of the side to move", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid session token", body = ErrorResponse),
+        (status = 404, description = "No game found", body = ErrorResponse),
+    ),
+    security(("session_token" = [])),
+)]
+#[get("/games/{game_id}/legal-moves", wrap = "from_fn(require_session)")]
+async fn legal_moves(path: web::Path<String>, query: web::Query<LegalMovesQuery>, player: web::ReqData<Player>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let piece = piece_coords(query.x, query.y, query.square)?;
+
+    Ok(HttpResponse::Ok().json(list_legal_moves(&pool, &path.into_inner(), piece, &player)?))
+}
+
+#[utoipa::path(
+    params(("game_id" = String, Path, description = "Id of the game")),
+    responses(
+        (status = 200, description = "State of the game after resigning", body = BoardInfoResponse),
+        (status = 401, description = "Missing or invalid session token", body = ErrorResponse),
+        (status = 404, description = "No game found", body = ErrorResponse),
+        (status = 409, description = "Game is over", body = ErrorResponse),
+    ),
+    security(("session_token" = [])),
+)]
+#[post("/games/{game_id}/resignation", wrap = "from_fn(require_session)")]
+async fn resign(path: web::Path<String>, player: web::ReqData<Player>, hub: web::Data<GameHub>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(resign_game(&pool, &path.into_inner(), &player, &hub).await?))
+}
+
+#[utoipa::path(
+    params(("game_id" = String, Path, description = "Id of the game")),
+    responses(
+        (status = 200, description = "State of the game after the offer, drawn if the opponent offered too or a bot accepted", body = BoardInfoResponse),
+        (status = 401, description = "Missing or invalid session token", body = ErrorResponse),
+        (status = 404, description = "No game found", body = ErrorResponse),
+        (status = 409, description = "Game is over, or a draw is already offered", body = ErrorResponse),
+    ),
+    security(("session_token" = [])),
+)]
+#[post("/games/{game_id}/draw-offer", wrap = "from_fn(require_session)")]
+async fn offer_draw(path: web::Path<String>, player: web::ReqData<Player>, hub: web::Data<GameHub>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(propose_draw(&pool, &path.into_inner(), &player, &hub).await?))
+}
+
+#[utoipa::path(
+    params(("game_id" = String, Path, description = "Id of the game")),
+    request_body = DrawResponseInfo,
+    responses(
+        (status = 200, description = "State of the game after the response", body = BoardInfoResponse),
+        (status = 401, description = "Missing or invalid session token", body = ErrorResponse),
+        (status = 404, description = "No game found", body = ErrorResponse),
+        (status = 409, description = "Game is over, or no draw is offered by the opponent", body = ErrorResponse),
+    ),
+    security(("session_token" = [])),
+)]
+#[post("/games/{game_id}/draw-offer/response", wrap = "from_fn(require_session)")]
+async fn respond_draw(path: web::Path<String>, response_info: web::Json<DrawResponseInfo>, player: web::ReqData<Player>, hub: web::Data<GameHub>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(answer_draw(&pool, &path.into_inner(), response_info.accept, &player, &hub).await?))
+}
+
+#[utoipa::path(
+    params(("game_id" = String, Path, description = "Id of the game"), LiveViewerQuery),
+    responses(
+        (status = 101, description = "WebSocket opened, every change of the game is sent as a GameUpdate message"),
+        (status = 401, description = "Neither a share token nor a session token", body = ErrorResponse),
+        (status = 404, description = "No game found, or not a player of the game, or the share link was revoked", body = ErrorResponse),
+    ),
+    security((), ("session_token" = [])),
+)]
+#[get("/games/{game_id}/live")]
+async fn live_updates(req: HttpRequest, body: web::Payload, path: web::Path<String>, viewer: web::Query<LiveViewerQuery>, hub: web::Data<GameHub>, pool: web::Data<DbPool>) -> Result<HttpResponse, actix_web::error::Error> {
+    open_live_updates(&pool, &req, body, path.into_inner(), &viewer, &hub).await
+}
+
+/*
+    Creates a read-only link for spectators, who pass its token as the share_token query parameter.
+*/
+#[utoipa::path(
+    params(("game_id" = String, Path, description = "Id of the game")),
+    responses(
+        (status = 201, description = "Share link created", body = ShareLink),
+        (status = 401, description = "Missing or invalid session token", body = ErrorResponse),
+        (status = 404, description = "No game found, or not a player of the game", body = ErrorResponse),
+    ),
+    security(("session_token" = [])),
+)]
+#[post("/games/{game_id}/share-links", wrap = "from_fn(require_session)")]
+async fn new_share_link(path: web::Path<String>, player: web::ReqData<Player>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let share_link = share_game(&pool, &path.into_inner(), &player)?;
+
+    Ok(HttpResponse::Created().json(share_link))
+}
+
+#[utoipa::path(
+    params(("game_id" = String, Path, description = "Id of the game")),
+    responses(
+        (status = 200, description = "Share links of the game that weren't revoked", body = Vec<ShareLink>),
+        (status = 401, description = "Missing or invalid session token", body = ErrorResponse),
+        (status = 404, description = "No game found, or not a player of the game", body = ErrorResponse),
+    ),
+    security(("session_token" = [])),
+)]
+#[get("/games/{game_id}/share-links", wrap = "from_fn(require_session)")]
+async fn share_links(path: web::Path<String>, player: web::ReqData<Player>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(list_share_links(&pool, &path.into_inner(), &player)?))
+}
+
+#[utoipa::path(
+    params(
+        ("game_id" = String, Path, description = "Id of the game"),
+        ("token" = String, Path, description = "Token of the share link"),
+    ),
+    responses(
+        (status = 204, description = "Share link revoked, its token no longer opens the game"),
+        (status = 401, description = "Missing or invalid session token", body = ErrorResponse),
+        (status = 404, description = "No game or share link found, or not a player of the game", body = ErrorResponse),
+    ),
+    security(("session_token" = [])),
+)]
+#[delete("/games/{game_id}/share-links/{token}", wrap = "from_fn(require_session)")]
+async fn delete_share_link(path: web::Path<(String, String)>, player: web::ReqData<Player>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let (game_id, token) = path.into_inner();
+    revoke_share_link(&pool, &game_id, &token, &player)?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/*
+    The same callers as for the game itself can export it.
+*/
+#[utoipa::path(
+    params(("game_id" = String, Path, description = "Id of the game"), ViewerQuery),
+    responses(
+        (status = 200, description = "The game as a record, with headers and the moves in tafl notation", body = String, content_type = "text/plain"),
+        (status = 401, description = "Neither a share token nor a session token", body = ErrorResponse),
+        (status = 404, description = "No game found, or not a player of the game, or the share link was revoked", body = ErrorResponse),
+        (status = 409, description = "The game started before its moves were stored", body = ErrorResponse),
+    ),
+    security((), ("session_token" = [])),
+)]
+#[get("/games/{game_id}/export")]
+async fn export_game(req: HttpRequest, path: web::Path<String>, viewer: web::Query<ViewerQuery>, hub: web::Data<GameHub>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let game_id = path.into_inner();
+    let record = export_record(&pool, &game_id, &req, &viewer, &hub).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .insert_header(("Content-Disposition", format!("attachment; filename=\"{}.otg\"", game_id)))
+        .body(record))
+}
+
+/*
+    The importing player plays both sides of the imported game, and can go on from where the record ends.
+*/
+#[utoipa::path(
+    request_body(content = String, content_type = "text/plain", description = "A game record, like the ones export returns"),
+    responses(
+        (status = 201, description = "Game imported", body = GameIdResponse),
+        (status = 400, description = "The record can't be read, or has an illegal move", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid session token", body = ErrorResponse),
+    ),
+    security(("session_token" = [])),
+)]
+#[post("/games/import", wrap = "from_fn(require_session)")]
+async fn import_game(record: String, player: web::ReqData<Player>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let game_id = import_record(&pool, &record, &player)?;
+
+    Ok(HttpResponse::Created()
+        .insert_header(("Location", format!("/api/v1/games/{}", game_id)))
+        .json(GameIdResponse { game_id }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utoipa::openapi::RefOr;
+
+    #[test]
+    fn test_openapi_document() {
+        let openapi = ApiDoc::openapi();
+
+        assert_eq!(openapi.paths.paths.len(), 21);
+        assert!(openapi.paths.paths["/games/{game_id}/moves"].get.is_some());
+
+        // Moves are posted as a MoveInfo
+        let play_move = openapi.paths.paths["/games/{game_id}/moves"].post.as_ref().unwrap();
+        let move_body = &play_move.request_body.as_ref().unwrap().content["application/json"];
+        match &move_body.schema {
+            Some(RefOr::Ref(schema)) => assert_eq!(schema.ref_location, "#/components/schemas/MoveInfo"),
+            _ => panic!("The move isn't a MoveInfo"),
+        }
+        assert!(openapi.paths.paths.contains_key("/games/{game_id}/draw-offer/response"));
+
+        let components = openapi.components.unwrap();
+        for schema in ["NewGameInfo", "MoveInfo", "BoardInfoResponse", "LegalMove", "TimeControl", "ErrorResponse", "GameUpdate"] {
+            assert!(components.schemas.contains_key(schema), "{} is missing", schema);
+        }
+        assert!(components.security_schemes.contains_key("session_token"));
+    }
+}
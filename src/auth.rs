@@ -0,0 +1,147 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web, HttpMessage, HttpRequest,
+};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use rand::{distributions::Alphanumeric, Rng};
+use rusqlite::Connection;
+
+use crate::{db::DbPool, error::ApiError};
+
+// Sessions are valid for 30 days after logging in
+const SESSION_LENGTH_SECS: u64 = 30 * 24 * 60 * 60;
+const TOKEN_LENGTH: usize = 48;
+
+/*
+    The player the request's session token belongs to.
+    Put into the request's extensions by require_session, handlers get it through web::ReqData.
+*/
+#[derive(Clone, Debug)]
+pub struct Player {
+    pub name: String,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+/*
+    Hashes the password with Argon2 and a random salt.
+    The salt and the parameters are stored together with the hash, in the PHC string format.
+*/
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    match Argon2::default().hash_password(password.as_bytes(), &salt) {
+        Ok(hash) => Ok(hash.to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    match PasswordHash::new(password_hash) {
+        Ok(parsed_hash) => Argon2::default().verify_password(password.as_bytes(), &parsed_hash).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/*
+    Starts a new session for the player, and returns its token.
+*/
+pub fn create_session(conn: &Connection, player_name: &str) -> rusqlite::Result<String> {
+    let token: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect();
+
+    conn.execute(
+        "INSERT INTO sessions (token, player_name, expires_at) VALUES (?1, ?2, ?3)",
+        rusqlite::params![token, player_name, now_secs() + SESSION_LENGTH_SECS],
+    )?;
+
+    Ok(token)
+}
+
+pub fn delete_session(conn: &Connection, token: &str) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM sessions WHERE token=?1", rusqlite::params![token])?;
+    Ok(())
+}
+
+/*
+    Returns the name of the player the token belongs to, or None if the token is unknown or expired.
+*/
+pub fn find_session_player(conn: &Connection, token: &str) -> rusqlite::Result<Option<String>> {
+    let result = conn.query_row(
+        "SELECT player_name FROM sessions WHERE token=?1 AND expires_at > ?2",
+        rusqlite::params![token, now_secs()],
+        |row| row.get(0),
+    );
+
+    match result {
+        Ok(player_name) => Ok(Some(player_name)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/*
+    Returns the token from an 'Authorization: Bearer <token>' header.
+*/
+pub fn bearer_token(req: &HttpRequest) -> Option<String> {
+    let header = req.headers().get("Authorization")?.to_str().ok()?;
+    header.strip_prefix("Bearer ").map(|token| token.trim().to_string())
+}
+
+/*
+    Middleware for the endpoints that act on behalf of a player.
+    Rejects requests without a valid session token, and stores the token's player for the handler.
+*/
+pub async fn require_session(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::error::Error> {
+    let token = match bearer_token(req.request()) {
+        Some(token) => token,
+        None => return Err(ApiError::MissingSessionToken.into()),
+    };
+
+    let conn = match req.app_data::<web::Data<DbPool>>() {
+        Some(pool) => pool.get().map_err(ApiError::from)?,
+        None => return Err(ApiError::Internal("No database pool".to_string()).into()),
+    };
+
+    match find_session_player(&conn, &token) {
+        Ok(Some(name)) => {
+            req.extensions_mut().insert(Player { name });
+        },
+        Ok(None) => return Err(ApiError::InvalidSessionToken.into()),
+        Err(err) => return Err(ApiError::Database(err).into()),
+    }
+
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password_hashing() {
+        let hash = hash_password("hunter2").unwrap();
+
+        assert!(verify_password("hunter2", &hash));
+        assert!(!verify_password("hunter3", &hash));
+        assert!(!verify_password("hunter2", "not a hash"));
+
+        // Every hash gets its own salt
+        assert_ne!(hash, hash_password("hunter2").unwrap());
+    }
+}
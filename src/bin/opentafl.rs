@@ -0,0 +1,48 @@
+use std::{env, io, process};
+
+use krabbi::{game::engine::{BotPlayer, parse_bot_player, parse_value}, opentafl::run};
+
+const USAGE: &str = "Usage: opentafl [options]
+
+Plays as an engine of OpenTafl, or of any other host that speaks the OpenTafl Engine Protocol,
+reading its commands from standard input and answering on standard output. Only Copenhagen rules
+are played.
+
+Options:
+    --engine <engine:difficulty>  Bot to play with (default minimax:3)
+
+Engines: random:1, minimax:1 to minimax:3. With a clock, minimax only searches as deep as the time allows.";
+
+fn parse_args() -> Result<BotPlayer, String> {
+    let mut engine = parse_bot_player("minimax:3")?;
+
+    let mut args = env::args().skip(1);
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--engine" => engine = parse_bot_player(&parse_value::<String>(&flag, args.next())?)?,
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            },
+            _ => return Err(format!("Unknown option '{}'", flag)),
+        }
+    }
+
+    Ok(engine)
+}
+
+fn main() {
+    let engine = match parse_args() {
+        Ok(engine) => engine,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            process::exit(2);
+        },
+    };
+
+    // Standard output belongs to the host, so failed commands are explained on standard error
+    if let Err(err) = run(engine, io::stdin().lock(), &mut io::stdout().lock(), &mut io::stderr().lock()) {
+        eprintln!("Engine stopped: {}", err);
+        process::exit(1);
+    }
+}
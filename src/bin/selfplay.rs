@@ -0,0 +1,100 @@
+use std::{env, fs::File, io::{self, BufWriter, Write}, process, thread};
+
+use krabbi::{
+    game::engine::{parse_bot_player, parse_value},
+    selfplay::{DEFAULT_RANDOM_PLIES, Format, SelfPlay, export_training_data},
+    tournament::DEFAULT_MAX_PLIES,
+};
+
+const USAGE: &str = "Usage: selfplay [options]
+
+Plays games between two bots, and writes every position with the side to move, the bot's search score,
+the move played and the game's result. Positions that were already written are skipped.
+
+Options:
+    --white <engine:difficulty>  Bot playing White (default minimax:2)
+    --black <engine:difficulty>  Bot playing Black (default minimax:2)
+    --games <n>                  Number of games to play (default 100)
+    --threads <n>                Number of games played at once (default: one per CPU)
+    --random-plies <n>           Random moves every game starts with, they aren't written (default 4)
+    --max-plies <n>              Draw games after this many moves (default 500)
+    --format <jsonl|csv>         Output format (default: csv for .csv files, jsonl otherwise)
+    --output <file>              File to write to (default: standard output)
+
+Engines: random:1, minimax:1 to minimax:3";
+
+fn parse_args() -> Result<(SelfPlay, Format, Option<String>), String> {
+    let default_bot = parse_bot_player("minimax:2")?;
+    let mut self_play = SelfPlay {
+        white: default_bot,
+        black: default_bot,
+        games: 100,
+        threads: thread::available_parallelism().map(|threads| threads.get()).unwrap_or(1),
+        random_plies: DEFAULT_RANDOM_PLIES,
+        max_plies: DEFAULT_MAX_PLIES,
+    };
+    let mut format = None;
+    let mut output: Option<String> = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--white" => self_play.white = parse_bot_player(&parse_value::<String>(&flag, args.next())?)?,
+            "--black" => self_play.black = parse_bot_player(&parse_value::<String>(&flag, args.next())?)?,
+            "--games" => self_play.games = parse_value(&flag, args.next())?,
+            "--threads" => self_play.threads = parse_value(&flag, args.next())?,
+            "--random-plies" => self_play.random_plies = parse_value(&flag, args.next())?,
+            "--max-plies" => self_play.max_plies = parse_value(&flag, args.next())?,
+            "--format" => {
+                let value = parse_value::<String>(&flag, args.next())?;
+                format = Some(Format::parse(&value).ok_or_else(|| format!("Unknown format '{}'", value))?);
+            },
+            "--output" => output = Some(parse_value(&flag, args.next())?),
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            },
+            _ => return Err(format!("Unknown option '{}'", flag)),
+        }
+    }
+
+    let format = format.unwrap_or(match &output {
+        Some(path) if path.ends_with(".csv") => Format::Csv,
+        _ => Format::Jsonl,
+    });
+
+    Ok((self_play, format, output))
+}
+
+fn main() {
+    let (self_play, format, output) = match parse_args() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            process::exit(2);
+        },
+    };
+
+    let mut out: Box<dyn Write> = match &output {
+        Some(path) => match File::create(path) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(err) => {
+                eprintln!("Could not create {}: {}", path, err);
+                process::exit(1);
+            },
+        },
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+
+    // The positions may be written to standard output, so the report goes to standard error
+    match export_training_data(&self_play, format, &mut out) {
+        Ok(report) => eprintln!(
+            "{} games, {} positions written, {} duplicate positions skipped",
+            report.games, report.positions, report.duplicates,
+        ),
+        Err(err) => {
+            eprintln!("Self-play failed: {}", err);
+            process::exit(1);
+        },
+    }
+}
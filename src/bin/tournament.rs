@@ -0,0 +1,133 @@
+use std::{env, fs, process, thread};
+
+use krabbi::{
+    game::{Game, engine::{parse_bot_player, parse_value}},
+    tournament::{DEFAULT_MAX_PLIES, Score, Sprt, SprtVerdict, Tournament, run_tournament},
+};
+
+const USAGE: &str = "Usage: tournament --first <engine:difficulty> --second <engine:difficulty> [options]
+
+Plays games between two bots, alternating sides, and reports how much stronger the first bot is.
+
+Options:
+    --games <n>          Number of games to play (default 100)
+    --threads <n>        Number of games played at once (default: one per CPU)
+    --openings <file>    Start from the positions in the file, one per line, in the game's string format
+    --max-plies <n>      Draw games after this many moves (default 500)
+    --elo0 <elo>         Elo difference of the SPRT's H0 (default 0)
+    --elo1 <elo>         Elo difference of the SPRT's H1 (default 20)
+    --alpha <p>          False positive rate of the SPRT (default 0.05)
+    --beta <p>           False negative rate of the SPRT (default 0.05)
+
+Engines: random:1, minimax:1 to minimax:3";
+
+/*
+    Reads the openings file. Empty lines and lines starting with '#' are skipped.
+*/
+fn load_openings(path: &str) -> Result<Vec<String>, String> {
+    let content = fs::read_to_string(path).map_err(|err| format!("Could not read {}: {}", path, err))?;
+
+    let openings: Vec<String> = content.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect();
+
+    for (index, opening) in openings.iter().enumerate() {
+        Game::from_string(opening.clone(), 1).map_err(|err| format!("Opening {} is invalid: {}", index + 1, err))?;
+    }
+
+    if openings.is_empty() {
+        return Err(format!("{} has no openings", path));
+    }
+
+    Ok(openings)
+}
+
+fn parse_args() -> Result<(Tournament, Sprt), String> {
+    let mut first = None;
+    let mut second = None;
+    let mut games = 100;
+    let mut threads = thread::available_parallelism().map(|threads| threads.get()).unwrap_or(1);
+    let mut openings = Vec::new();
+    let mut max_plies = DEFAULT_MAX_PLIES;
+    let mut sprt = Sprt::default();
+
+    let mut args = env::args().skip(1);
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--first" => first = Some(parse_bot_player(&parse_value::<String>(&flag, args.next())?)?),
+            "--second" => second = Some(parse_bot_player(&parse_value::<String>(&flag, args.next())?)?),
+            "--games" => games = parse_value(&flag, args.next())?,
+            "--threads" => threads = parse_value(&flag, args.next())?,
+            "--openings" => openings = load_openings(&parse_value::<String>(&flag, args.next())?)?,
+            "--max-plies" => max_plies = parse_value(&flag, args.next())?,
+            "--elo0" => sprt.elo0 = parse_value(&flag, args.next())?,
+            "--elo1" => sprt.elo1 = parse_value(&flag, args.next())?,
+            "--alpha" => sprt.alpha = parse_value(&flag, args.next())?,
+            "--beta" => sprt.beta = parse_value(&flag, args.next())?,
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            },
+            _ => return Err(format!("Unknown option '{}'", flag)),
+        }
+    }
+
+    let (Some(first), Some(second)) = (first, second) else {
+        return Err("Both --first and --second are needed".to_string());
+    };
+
+    Ok((Tournament { first, second, games, threads, openings, max_plies }, sprt))
+}
+
+fn print_score(score: &Score) {
+    let elo = match score.elo_difference() {
+        Some((elo, margin)) => format!("{:+.1} +/- {:.1}", elo, margin),
+        None => "n/a".to_string(),
+    };
+
+    println!(
+        "Games: {}  W: {}  D: {}  L: {}  Elo: {}",
+        score.games(), score.wins, score.draws, score.losses, elo,
+    );
+}
+
+fn main() {
+    let (tournament, sprt) = match parse_args() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            process::exit(2);
+        },
+    };
+
+    println!(
+        "{} {} vs {} {}, {} games on {} threads",
+        tournament.first.engine.as_str(), tournament.first.difficulty,
+        tournament.second.engine.as_str(), tournament.second.difficulty,
+        tournament.games, tournament.threads,
+    );
+
+    let score = match run_tournament(&tournament, print_score) {
+        Ok(score) => score,
+        Err(err) => {
+            eprintln!("Tournament failed: {}", err);
+            process::exit(1);
+        },
+    };
+
+    println!();
+    print_score(&score);
+
+    let (lower, upper) = sprt.bounds();
+    let verdict = match sprt.verdict(&score) {
+        SprtVerdict::AcceptH1 => format!("H1 accepted, the first bot is at least {} Elo stronger", sprt.elo1),
+        SprtVerdict::AcceptH0 => format!("H0 accepted, the first bot is at most {} Elo stronger", sprt.elo0),
+        SprtVerdict::Continue => "no verdict yet, play more games".to_string(),
+    };
+    println!(
+        "SPRT [{}, {}] (alpha {}, beta {}): LLR {:.2} ({:.2}, {:.2}), {}",
+        sprt.elo0, sprt.elo1, sprt.alpha, sprt.beta, sprt.llr(&score), lower, upper, verdict,
+    );
+}
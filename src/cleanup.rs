@@ -0,0 +1,229 @@
+use std::time::Duration;
+
+use actix_web::web;
+use rusqlite::{Connection, TransactionBehavior};
+
+use crate::{
+    clock::{load_clock, now_ms},
+    db::DbPool,
+    error::ApiError,
+    games::{check_flag, load_game, state_update},
+    live::{GameEvent, GameHub, GameUpdate},
+};
+
+pub const DEFAULT_ABANDONED_GAME_DAYS: u64 = 30;
+pub const DEFAULT_CLEANUP_INTERVAL_MINUTES: u64 = 60;
+
+// Every table with rows that belong to a game, the games table itself is cleaned up last
+const GAME_TABLES: &[&str] = &["moves", "game_players", "game_clocks", "game_results", "draw_offers", "share_links", "exhibitions", "imported_games"];
+
+pub struct CleanupReport {
+    // The games that were lost on time, with their final state
+    pub flagged: Vec<(String, GameUpdate)>,
+    pub deleted: usize,
+}
+
+/*
+    Ends the timed games whose side to move has run out of time, and returns their final state.
+    Flags are also checked whenever a game is loaded, but correspondence games may not be looked at
+    for days after a player stopped moving. Games still waiting for an opponent are left alone.
+    Every game is flagged in a transaction of its own, under the write lock of the database, so a game
+    is either ended and rated, or not at all, and moves made meanwhile are seen.
+*/
+pub fn flag_expired_games(conn: &mut Connection) -> Result<Vec<(String, GameUpdate)>, ApiError> {
+    let game_ids = {
+        let mut statement = conn.prepare(
+            "SELECT games.id FROM games
+                JOIN game_clocks ON game_clocks.game_id = games.id
+                WHERE games.winner = 'x'
+                AND NOT EXISTS (SELECT 1 FROM game_players WHERE game_id = games.id AND player_name IS NULL)"
+        )?;
+
+        let game_ids = statement.query_map([], |row| row.get::<usize, String>(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        game_ids
+    };
+
+    let mut flagged = Vec::new();
+
+    for game_id in game_ids {
+        let transaction = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        // A game that can't be loaded can't be flagged either, it shouldn't stop the others
+        let Ok(mut game) = load_game(&transaction, &game_id) else {
+            continue;
+        };
+        let clock = load_clock(&transaction, &game_id)?;
+
+        if check_flag(&transaction, &game_id, &mut game, &clock, now_ms())? {
+            transaction.commit()?;
+            flagged.push((game_id, state_update(&game, clock, GameEvent::GameOver)));
+        }
+    }
+
+    Ok(flagged)
+}
+
+/*
+    Deletes the unfinished games that nobody touched for longer than max_inactive_ms, and returns how many.
+    Timed games are flagged instead, unless their clock never started because they wait for an opponent.
+    Finished games are kept, the statistics and ratings are made of them.
+*/
+pub fn delete_abandoned_games(conn: &mut Connection, now: u64, max_inactive_ms: u64) -> rusqlite::Result<usize> {
+    let transaction = conn.transaction()?;
+
+    let game_ids = {
+        let mut statement = transaction.prepare(
+            "SELECT id FROM games
+                WHERE winner = 'x' AND updated_at < ?1
+                AND (
+                    id NOT IN (SELECT game_id FROM game_clocks)
+                    OR EXISTS (SELECT 1 FROM game_players WHERE game_id = games.id AND player_name IS NULL)
+                )"
+        )?;
+
+        let game_ids = statement.query_map(rusqlite::params![now.saturating_sub(max_inactive_ms)], |row| row.get::<usize, String>(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        game_ids
+    };
+
+    for game_id in &game_ids {
+        for table in GAME_TABLES {
+            transaction.execute(&format!("DELETE FROM {} WHERE game_id=?1", table), rusqlite::params![game_id])?;
+        }
+
+        transaction.execute("DELETE FROM games WHERE id=?1", rusqlite::params![game_id])?;
+    }
+
+    transaction.commit()?;
+
+    Ok(game_ids.len())
+}
+
+fn clean_up(pool: &DbPool, max_inactive_ms: u64) -> Result<CleanupReport, ApiError> {
+    let mut conn = pool.get()?;
+
+    let flagged = flag_expired_games(&mut conn)?;
+    let deleted = delete_abandoned_games(&mut conn, now_ms(), max_inactive_ms)?;
+
+    Ok(CleanupReport { flagged, deleted })
+}
+
+/*
+    Cleans up the games every interval, for as long as the server runs.
+    Players watching a game that was lost on time get its final state pushed.
+*/
+pub fn spawn_cleanup(pool: DbPool, hub: web::Data<GameHub>, interval: Duration, max_inactive: Duration) {
+    actix_web::rt::spawn(async move {
+        let mut ticks = actix_web::rt::time::interval(interval);
+
+        loop {
+            ticks.tick().await;
+
+            let cleanup_pool = pool.clone();
+            match web::block(move || clean_up(&cleanup_pool, max_inactive.as_millis() as u64)).await {
+                Ok(Ok(report)) => {
+                    for (game_id, update) in &report.flagged {
+                        hub.publish(game_id, update).await;
+                    }
+
+                    if !report.flagged.is_empty() || report.deleted > 0 {
+                        println!("Cleanup: {} games lost on time, {} abandoned games deleted", report.flagged.len(), report.deleted);
+                    }
+                },
+                Ok(Err(err)) => println!("Cleanup failed: {}", err),
+                Err(err) => println!("Cleanup could not be scheduled: {:?}", err),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::{Clock, TimeControl, save_clock},
+        db::migrate,
+        game::Game,
+    };
+
+    const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+    fn add_game(conn: &Connection, game_id: &str, seats: &[(&str, Option<&str>)], winner: &str, updated_at: u64) {
+        let game_state = Game::starting_position(false, false, 1).to_string().unwrap();
+
+        conn.execute(
+            "INSERT INTO games (id, game_state, player_name, winner, bot_difficulty, created_at, updated_at)
+                VALUES (?1, ?2, 'alice', ?3, 1, ?4, ?4)",
+            rusqlite::params![game_id, game_state, winner, updated_at],
+        ).unwrap();
+
+        for (color, player_name) in seats {
+            conn.execute(
+                "INSERT INTO game_players (game_id, color, player_name) VALUES (?1, ?2, ?3)",
+                rusqlite::params![game_id, color, player_name],
+            ).unwrap();
+        }
+    }
+
+    fn game_ids(conn: &Connection) -> Vec<String> {
+        let mut statement = conn.prepare("SELECT id FROM games ORDER BY id").unwrap();
+        statement.query_map([], |row| row.get(0)).unwrap().collect::<rusqlite::Result<Vec<String>>>().unwrap()
+    }
+
+    #[test]
+    fn test_flag_expired_games() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+
+        let now = now_ms();
+        let players = [("w", Some("alice")), ("b", Some("bob"))];
+        let correspondence = TimeControl::Correspondence { days_per_move: 2 };
+
+        add_game(&conn, "expired", &players, "x", now - 3 * DAY_MS);
+        save_clock(&conn, "expired", &Clock::new(correspondence, now - 3 * DAY_MS)).unwrap();
+        add_game(&conn, "running", &players, "x", now - DAY_MS);
+        save_clock(&conn, "running", &Clock::new(correspondence, now - DAY_MS)).unwrap();
+        add_game(&conn, "waiting", &[("w", Some("alice")), ("b", None)], "x", now - 3 * DAY_MS);
+        save_clock(&conn, "waiting", &Clock::new(correspondence, now - 3 * DAY_MS)).unwrap();
+
+        let flagged = flag_expired_games(&mut conn).unwrap();
+        assert_eq!(flagged.len(), 1);
+        assert_eq!(flagged[0].0, "expired");
+        // Black moves first, and didn't
+        assert_eq!(flagged[0].1.winner, "w");
+
+        let winner: String = conn.query_row("SELECT winner FROM games WHERE id='expired'", [], |row| row.get(0)).unwrap();
+        assert_eq!(winner, "w");
+        assert!(flag_expired_games(&mut conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_delete_abandoned_games() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+
+        let now = 100 * DAY_MS;
+        let players = [("w", Some("alice")), ("b", Some("bob"))];
+
+        add_game(&conn, "abandoned", &players, "x", now - 31 * DAY_MS);
+        crate::moves::record_move(&conn, "abandoned", crate::game::board::Color::Black, (0, 3), (1, 3), &[], now - 31 * DAY_MS).unwrap();
+        add_game(&conn, "recent", &players, "x", now - DAY_MS);
+        add_game(&conn, "finished", &players, "w", now - 31 * DAY_MS);
+        add_game(&conn, "never_joined", &[("w", Some("alice")), ("b", None)], "x", now - 31 * DAY_MS);
+        save_clock(&conn, "never_joined", &Clock::new(TimeControl::Correspondence { days_per_move: 1 }, now - 31 * DAY_MS)).unwrap();
+        // Timed games are lost on time instead
+        add_game(&conn, "timed", &players, "x", now - 31 * DAY_MS);
+        save_clock(&conn, "timed", &Clock::new(TimeControl::Correspondence { days_per_move: 1 }, now - 31 * DAY_MS)).unwrap();
+
+        assert_eq!(delete_abandoned_games(&mut conn, now, 30 * DAY_MS).unwrap(), 2);
+        assert_eq!(game_ids(&conn), vec!["finished", "recent", "timed"]);
+
+        let leftover_rows: u32 = conn.query_row(
+            "SELECT (SELECT COUNT(*) FROM moves) + (SELECT COUNT(*) FROM game_players WHERE game_id IN ('abandoned', 'never_joined'))",
+            [],
+            |row| row.get(0),
+        ).unwrap();
+        assert_eq!(leftover_rows, 0);
+    }
+}
@@ -0,0 +1,322 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{error::ApiError, game::board::Color};
+
+const MS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
+// Bots never think longer than this, even with days left on the clock
+const MAX_BOT_BUDGET_MS: u64 = 10_000;
+// A bot with main time left plans for this many more moves
+const BOT_MOVES_TO_PLAN_FOR: u64 = 30;
+// The longest time controls a game can be created with
+const MAX_MAIN_SECS: u64 = 24 * 60 * 60;
+// The longest increment, and the longest byoyomi period
+const MAX_INCREMENT_SECS: u64 = 60 * 60;
+const MAX_PERIODS: u32 = 30;
+const MAX_DAYS_PER_MOVE: u64 = 30;
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TimeControl {
+    // Main time, plus an increment added after every move
+    Fischer { initial_secs: u64, increment_secs: u64 },
+    // Main time, then a number of periods; a period is only used up if a move takes longer than it
+    Byoyomi { main_secs: u64, period_secs: u64, periods: u32 },
+    // A fixed time for every single move
+    Correspondence { days_per_move: u64 },
+}
+
+impl TimeControl {
+    /*
+        Checks that a game can be played with the time control: no side may be out of time before its first move,
+        and no time may be longer than the limits above.
+        Byoyomi may go without main time, but needs periods to play in.
+    */
+    pub fn validate(&self) -> Result<(), ApiError> {
+        match *self {
+            TimeControl::Fischer { initial_secs, increment_secs } => {
+                if !(1..=MAX_MAIN_SECS).contains(&initial_secs) {
+                    return Err(ApiError::InvalidRequest(format!("initial_secs must be between 1 and {}", MAX_MAIN_SECS)));
+                }
+                if increment_secs > MAX_INCREMENT_SECS {
+                    return Err(ApiError::InvalidRequest(format!("increment_secs must be at most {}", MAX_INCREMENT_SECS)));
+                }
+            },
+            TimeControl::Byoyomi { main_secs, period_secs, periods } => {
+                if main_secs > MAX_MAIN_SECS {
+                    return Err(ApiError::InvalidRequest(format!("main_secs must be at most {}", MAX_MAIN_SECS)));
+                }
+                if !(1..=MAX_INCREMENT_SECS).contains(&period_secs) {
+                    return Err(ApiError::InvalidRequest(format!("period_secs must be between 1 and {}", MAX_INCREMENT_SECS)));
+                }
+                if !(1..=MAX_PERIODS).contains(&periods) {
+                    return Err(ApiError::InvalidRequest(format!("periods must be between 1 and {}", MAX_PERIODS)));
+                }
+            },
+            TimeControl::Correspondence { days_per_move } => {
+                if !(1..=MAX_DAYS_PER_MOVE).contains(&days_per_move) {
+                    return Err(ApiError::InvalidRequest(format!("days_per_move must be between 1 and {}", MAX_DAYS_PER_MOVE)));
+                }
+            },
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug, ToSchema)]
+pub struct SideClock {
+    pub remaining_ms: u64,
+    pub periods_left: u32,
+}
+
+/*
+    The clocks of both sides of a game. Only the side to move has a running clock,
+    it has been running since turn_started_at_ms.
+*/
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug, ToSchema)]
+pub struct Clock {
+    pub time_control: TimeControl,
+    pub white: SideClock,
+    pub black: SideClock,
+    pub turn_started_at_ms: u64,
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+impl Clock {
+    pub fn new(time_control: TimeControl, now: u64) -> Clock {
+        let side_clock = match time_control {
+            TimeControl::Fischer { initial_secs, .. } => SideClock {
+                remaining_ms: initial_secs.saturating_mul(1000),
+                periods_left: 0,
+            },
+            TimeControl::Byoyomi { main_secs, periods, .. } => SideClock {
+                remaining_ms: main_secs.saturating_mul(1000),
+                periods_left: periods,
+            },
+            TimeControl::Correspondence { days_per_move } => SideClock {
+                remaining_ms: days_per_move.saturating_mul(MS_PER_DAY),
+                periods_left: 0,
+            },
+        };
+
+        Clock {
+            time_control,
+            white: side_clock,
+            black: side_clock,
+            turn_started_at_ms: now,
+        }
+    }
+
+    fn side(&self, color: Color) -> &SideClock {
+        if color == Color::White {
+            &self.white
+        } else {
+            &self.black
+        }
+    }
+
+    fn side_mut(&mut self, color: Color) -> &mut SideClock {
+        if color == Color::White {
+            &mut self.white
+        } else {
+            &mut self.black
+        }
+    }
+
+    /*
+        Returns how much time the side to move has left for its current move, at the time 'now'.
+    */
+    pub fn time_left_ms(&self, color: Color, now: u64) -> u64 {
+        let side = self.side(color);
+        let elapsed = now.saturating_sub(self.turn_started_at_ms);
+
+        let available = match self.time_control {
+            TimeControl::Byoyomi { period_secs, .. } => {
+                side.remaining_ms.saturating_add(period_secs.saturating_mul(1000).saturating_mul(u64::from(side.periods_left)))
+            },
+            _ => side.remaining_ms,
+        };
+
+        available.saturating_sub(elapsed)
+    }
+
+    pub fn is_flagged(&self, color: Color, now: u64) -> bool {
+        self.time_left_ms(color, now) == 0
+    }
+
+    /*
+        Ends the turn of 'color' at the time 'now': takes the time it used off its clock,
+        and starts the clock of the other side.
+        Returns Err if the side ran out of time before the end of its turn.
+    */
+    pub fn end_turn(&mut self, color: Color, now: u64) -> Result<(), &'static str> {
+        if self.is_flagged(color, now) {
+            return Err("Out of time!");
+        }
+
+        let elapsed = now.saturating_sub(self.turn_started_at_ms);
+        let time_control = self.time_control;
+        let side = self.side_mut(color);
+
+        match time_control {
+            TimeControl::Fischer { increment_secs, .. } => {
+                side.remaining_ms = side.remaining_ms.saturating_sub(elapsed).saturating_add(increment_secs.saturating_mul(1000));
+            },
+            TimeControl::Byoyomi { period_secs, .. } => {
+                if elapsed <= side.remaining_ms {
+                    side.remaining_ms -= elapsed;
+                } else {
+                    let overtime = elapsed - side.remaining_ms;
+                    side.remaining_ms = 0;
+                    let periods_used = overtime.checked_div(period_secs.saturating_mul(1000)).unwrap_or(0);
+                    side.periods_left = side.periods_left.saturating_sub(u32::try_from(periods_used).unwrap_or(u32::MAX));
+                }
+            },
+            TimeControl::Correspondence { days_per_move } => {
+                side.remaining_ms = days_per_move.saturating_mul(MS_PER_DAY);
+            },
+        }
+
+        self.turn_started_at_ms = now;
+        Ok(())
+    }
+
+    /*
+        How long a bot playing 'color' may think about its move.
+    */
+    pub fn bot_budget(&self, color: Color, now: u64) -> Duration {
+        let side = self.side(color);
+        let time_left = self.time_left_ms(color, now);
+
+        let budget_ms = match self.time_control {
+            TimeControl::Fischer { increment_secs, .. } => {
+                time_left / BOT_MOVES_TO_PLAN_FOR + increment_secs.saturating_mul(1000) / 5 * 4
+            },
+            TimeControl::Byoyomi { period_secs, .. } => {
+                let period_budget_ms = period_secs.saturating_mul(1000) / 5 * 4;
+
+                if side.remaining_ms > 0 {
+                    side.remaining_ms / BOT_MOVES_TO_PLAN_FOR + period_budget_ms
+                } else {
+                    period_budget_ms
+                }
+            },
+            TimeControl::Correspondence { .. } => MAX_BOT_BUDGET_MS,
+        };
+
+        // Never plan to use up the whole clock
+        Duration::from_millis(budget_ms.min(time_left / 2).min(MAX_BOT_BUDGET_MS))
+    }
+}
+
+pub fn load_clock(conn: &Connection, game_id: &str) -> rusqlite::Result<Option<Clock>> {
+    let result = conn.query_row(
+        "SELECT clock FROM game_clocks WHERE game_id=?1",
+        rusqlite::params![game_id],
+        |row| row.get::<usize, String>(0),
+    );
+
+    match result {
+        Ok(clock_json) => Ok(serde_json::from_str(&clock_json).ok()),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+pub fn save_clock(conn: &Connection, game_id: &str, clock: &Clock) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO game_clocks (game_id, clock) VALUES (?1, ?2)",
+        rusqlite::params![game_id, serde_json::to_string(clock).unwrap()],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fischer_increment() {
+        let mut clock = Clock::new(TimeControl::Fischer { initial_secs: 60, increment_secs: 2 }, 0);
+
+        assert!(clock.end_turn(Color::Black, 10_000).is_ok());
+        assert_eq!(clock.black.remaining_ms, 52_000);
+        assert_eq!(clock.white.remaining_ms, 60_000);
+
+        assert_eq!(clock.time_left_ms(Color::White, 30_000), 40_000);
+        assert!(clock.is_flagged(Color::White, 70_000));
+        assert!(clock.end_turn(Color::White, 70_000).is_err());
+    }
+
+    #[test]
+    fn test_byoyomi_periods() {
+        let mut clock = Clock::new(TimeControl::Byoyomi { main_secs: 10, period_secs: 5, periods: 3 }, 0);
+
+        // Uses up the main time, and part of the first period, which is kept
+        assert!(clock.end_turn(Color::Black, 14_000).is_ok());
+        assert_eq!(clock.black.remaining_ms, 0);
+        assert_eq!(clock.black.periods_left, 3);
+
+        clock.end_turn(Color::White, 15_000).unwrap();
+
+        // Takes longer than a period, which uses it up
+        assert!(clock.end_turn(Color::Black, 22_000).is_ok());
+        assert_eq!(clock.black.periods_left, 2);
+
+        clock.end_turn(Color::White, 22_000).unwrap();
+
+        assert_eq!(clock.time_left_ms(Color::Black, 22_000), 10_000);
+        assert!(clock.is_flagged(Color::Black, 32_000));
+    }
+
+    #[test]
+    fn test_correspondence_resets_every_move() {
+        let mut clock = Clock::new(TimeControl::Correspondence { days_per_move: 2 }, 0);
+
+        assert!(clock.end_turn(Color::Black, MS_PER_DAY).is_ok());
+        assert_eq!(clock.black.remaining_ms, 2 * MS_PER_DAY);
+        assert!(clock.is_flagged(Color::White, 3 * MS_PER_DAY + 1));
+        assert!(!clock.is_flagged(Color::White, 3 * MS_PER_DAY - 1));
+    }
+
+    #[test]
+    fn test_validate_time_control() {
+        assert!(TimeControl::Fischer { initial_secs: 300, increment_secs: 0 }.validate().is_ok());
+        assert!(TimeControl::Byoyomi { main_secs: 0, period_secs: 30, periods: 5 }.validate().is_ok());
+
+        // Lost on time before the first move
+        assert!(TimeControl::Fischer { initial_secs: 0, increment_secs: 5 }.validate().is_err());
+        assert!(TimeControl::Byoyomi { main_secs: 60, period_secs: 0, periods: 5 }.validate().is_err());
+        assert!(TimeControl::Correspondence { days_per_move: 0 }.validate().is_err());
+
+        // Too long, and too large to count in milliseconds
+        assert!(TimeControl::Fischer { initial_secs: u64::MAX, increment_secs: 0 }.validate().is_err());
+        assert!(TimeControl::Byoyomi { main_secs: 60, period_secs: 30, periods: u32::MAX }.validate().is_err());
+        assert!(TimeControl::Correspondence { days_per_move: u64::MAX / 1000 }.validate().is_err());
+    }
+
+    #[test]
+    fn test_huge_clocks_saturate() {
+        let mut clock = Clock::new(TimeControl::Byoyomi { main_secs: u64::MAX, period_secs: u64::MAX, periods: u32::MAX }, 0);
+
+        assert_eq!(clock.time_left_ms(Color::Black, 0), u64::MAX);
+        assert!(clock.end_turn(Color::Black, 1_000).is_ok());
+        clock.bot_budget(Color::White, 1_000);
+    }
+
+    #[test]
+    fn test_bot_budget_fits_clock() {
+        let clock = Clock::new(TimeControl::Fischer { initial_secs: 3, increment_secs: 0 }, 0);
+        assert_eq!(clock.bot_budget(Color::Black, 0), Duration::from_millis(100));
+
+        let clock = Clock::new(TimeControl::Correspondence { days_per_move: 1 }, 0);
+        assert_eq!(clock.bot_budget(Color::Black, 0), Duration::from_millis(MAX_BOT_BUDGET_MS));
+    }
+}
@@ -0,0 +1,321 @@
+use r2d2::{Pool, PooledConnection};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::Connection;
+
+// Used when DATABASE_PATH isn't set, so existing installs keep their games
+pub const DEFAULT_DB_PATH: &str = "test.db";
+// How long a connection waits for another one's write to finish, before giving up
+const BUSY_TIMEOUT_MS: u32 = 5_000;
+
+pub type DbPool = Pool<SqliteConnectionManager>;
+pub type DbConnection = PooledConnection<SqliteConnectionManager>;
+
+/*
+    Opens a pool of connections to the database at the given path.
+    The database is switched to WAL mode, so readers don't block the writer and the other way around.
+*/
+pub fn open_pool(path: &str) -> Result<DbPool, r2d2::Error> {
+    let manager = SqliteConnectionManager::file(path).with_init(|conn| {
+        conn.execute_batch(&format!(
+            "PRAGMA journal_mode = WAL;
+            PRAGMA busy_timeout = {};",
+            BUSY_TIMEOUT_MS,
+        ))
+    });
+
+    Pool::new(manager)
+}
+
+/*
+    The changes to the schema, in the order they are applied. The database's user_version
+    is the number of migrations it has already been through, so every migration runs once.
+    Released migrations must never be edited, changes to the schema go into a new one.
+    Databases from before the migrations have user_version 0, but may already have some of
+    the tables, which is why the first migrations only create tables that don't exist yet.
+*/
+const MIGRATIONS: &[&str] = &[
+    // 1: the original games table
+    "CREATE TABLE IF NOT EXISTS games (
+        id TEXT PRIMARY KEY UNIQUE,
+        game_state TEXT,
+        player_name TEXT,
+        winner TEXT,
+        bot_difficulty INTEGER
+    );",
+    // 2: accounts, seats of online games, clocks, results and draw offers
+    "CREATE TABLE IF NOT EXISTS game_players (
+        game_id TEXT,
+        color TEXT,
+        player_name TEXT,
+        invite_code TEXT UNIQUE,
+        PRIMARY KEY (game_id, color)
+    );
+
+    CREATE TABLE IF NOT EXISTS players (
+        name TEXT PRIMARY KEY UNIQUE,
+        password_hash TEXT
+    );
+
+    CREATE TABLE IF NOT EXISTS sessions (
+        token TEXT PRIMARY KEY UNIQUE,
+        player_name TEXT,
+        expires_at INTEGER
+    );
+
+    CREATE TABLE IF NOT EXISTS game_clocks (
+        game_id TEXT PRIMARY KEY UNIQUE,
+        clock TEXT
+    );
+
+    CREATE TABLE IF NOT EXISTS game_results (
+        game_id TEXT PRIMARY KEY UNIQUE,
+        reason TEXT
+    );
+
+    CREATE TABLE IF NOT EXISTS draw_offers (
+        game_id TEXT PRIMARY KEY UNIQUE,
+        color TEXT
+    );",
+    // 3: the moves of every game, so games can be replayed
+    "CREATE TABLE moves (
+        game_id TEXT NOT NULL,
+        ply INTEGER NOT NULL,
+        from_x INTEGER NOT NULL,
+        from_y INTEGER NOT NULL,
+        to_x INTEGER NOT NULL,
+        to_y INTEGER NOT NULL,
+        captures TEXT NOT NULL,
+        played_at INTEGER NOT NULL,
+        PRIMARY KEY (game_id, ply)
+    );",
+    // 4: the number of moves played, which every update of a game checks, and increments
+    "ALTER TABLE games ADD COLUMN ply INTEGER NOT NULL DEFAULT 0;
+
+    UPDATE games SET ply = (SELECT COUNT(*) FROM moves WHERE moves.game_id = games.id);",
+    // 5: Glicko-2 ratings of players and bots, and how they changed with every rated game
+    "CREATE TABLE ratings (
+        player_name TEXT PRIMARY KEY,
+        rating REAL NOT NULL,
+        deviation REAL NOT NULL,
+        volatility REAL NOT NULL
+    );
+
+    CREATE TABLE rating_history (
+        player_name TEXT NOT NULL,
+        game_id TEXT NOT NULL,
+        rating REAL NOT NULL,
+        deviation REAL NOT NULL,
+        volatility REAL NOT NULL,
+        rated_at INTEGER NOT NULL,
+        PRIMARY KEY (player_name, game_id)
+    );",
+    // 6: when games were created and last changed, taken from their moves for older games
+    "ALTER TABLE games ADD COLUMN created_at INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE games ADD COLUMN updated_at INTEGER NOT NULL DEFAULT 0;
+
+    UPDATE games SET
+        created_at = COALESCE(
+            (SELECT MIN(played_at) FROM moves WHERE moves.game_id = games.id),
+            CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+        ),
+        updated_at = COALESCE(
+            (SELECT MAX(played_at) FROM moves WHERE moves.game_id = games.id),
+            CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)
+        );",
+    // 7: read-only links to games, for spectators
+    "CREATE TABLE share_links (
+        token TEXT PRIMARY KEY,
+        game_id TEXT NOT NULL,
+        created_by TEXT NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+
+    CREATE INDEX share_links_game_id ON share_links (game_id);",
+    // 8: games between two bots, with the engine of each side
+    "CREATE TABLE exhibitions (
+        game_id TEXT PRIMARY KEY,
+        white_engine TEXT NOT NULL,
+        white_difficulty INTEGER NOT NULL,
+        black_engine TEXT NOT NULL,
+        black_difficulty INTEGER NOT NULL,
+        move_delay_ms INTEGER NOT NULL
+    );",
+    // 9: the players and date of imported game records, the importing player plays both sides
+    "CREATE TABLE imported_games (
+        game_id TEXT PRIMARY KEY,
+        white TEXT,
+        black TEXT,
+        date TEXT
+    );",
+    // 10: the games of a player are looked up by name
+    "CREATE INDEX games_player_name ON games (player_name);
+
+    CREATE INDEX game_players_player_name ON game_players (player_name);",
+    // 11: the side that played every move. It stays unknown for the moves stored before,
+    // the plies of games from before the moves table don't tell whose move they were.
+    "ALTER TABLE moves ADD COLUMN color TEXT;",
+    // 12: whether bots play the sides of a game, taken from the state of the older games,
+    // where the third and second to last characters are 'b' for a bot
+    "ALTER TABLE games ADD COLUMN white_is_bot INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE games ADD COLUMN black_is_bot INTEGER NOT NULL DEFAULT 0;
+
+    UPDATE games SET
+        white_is_bot = substr(game_state, -3, 1) = 'b',
+        black_is_bot = substr(game_state, -2, 1) = 'b';",
+    // 13: the player who started every exhibition, unknown for the older ones
+    "ALTER TABLE exhibitions ADD COLUMN started_by TEXT;",
+];
+
+pub fn schema_version(conn: &Connection) -> rusqlite::Result<usize> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+/*
+    Brings the schema up to date, and returns its version.
+    Every migration runs in its own transaction, together with the update of the version,
+    so a failed migration leaves the database as it was before it.
+*/
+pub fn migrate(conn: &mut Connection) -> Result<usize, String> {
+    let version = schema_version(conn).map_err(|err| err.to_string())?;
+
+    if version > MIGRATIONS.len() {
+        return Err(format!(
+            "Database schema version {} is newer than the latest known version {}",
+            version,
+            MIGRATIONS.len(),
+        ));
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let new_version = index + 1;
+
+        let transaction = conn.transaction().map_err(|err| err.to_string())?;
+        transaction.execute_batch(migration)
+            .and_then(|_| transaction.pragma_update(None, "user_version", new_version))
+            .and_then(|_| transaction.commit())
+            .map_err(|err| format!("Migration {} failed: {}", new_version, err))?;
+
+        println!("Migrated database to schema version {}", new_version);
+    }
+
+    Ok(MIGRATIONS.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The schema of the databases from before the migrations
+    const BASELINE_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS games (
+        id TEXT PRIMARY KEY UNIQUE,
+        game_state TEXT,
+        player_name TEXT,
+        winner TEXT,
+        bot_difficulty INTEGER
+    );";
+
+    fn table_names(conn: &Connection) -> Vec<String> {
+        let mut statement = conn.prepare("SELECT name FROM sqlite_master WHERE type='table' ORDER BY name").unwrap();
+        statement.query_map([], |row| row.get(0)).unwrap().collect::<rusqlite::Result<Vec<String>>>().unwrap()
+    }
+
+    #[test]
+    fn test_open_pool() {
+        let path = std::env::temp_dir().join(format!("krabbi-{}.db", uuid::Uuid::new_v4()));
+        let pool = open_pool(path.to_str().unwrap()).unwrap();
+
+        let mut conn = pool.get().unwrap();
+        let journal_mode: String = conn.query_row("PRAGMA journal_mode", [], |row| row.get(0)).unwrap();
+        assert_eq!(journal_mode, "wal");
+
+        migrate(&mut conn).unwrap();
+
+        // Other connections of the pool see the migrated schema
+        let other_conn = pool.get().unwrap();
+        assert_eq!(schema_version(&other_conn).unwrap(), MIGRATIONS.len());
+
+        drop((conn, other_conn, pool));
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.to_str().unwrap(), suffix));
+        }
+    }
+
+    #[test]
+    fn test_migrate_from_baseline() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(BASELINE_SCHEMA).unwrap();
+        conn.execute(
+            "INSERT INTO games (id, game_state, player_name, winner, bot_difficulty) VALUES ('game', 'fen', 'alice', 'x', 1)",
+            [],
+        ).unwrap();
+        conn.execute(
+            "INSERT INTO games (id, game_state, player_name, winner, bot_difficulty) VALUES ('bot game', 'fen/bpb', 'alice', 'x', 1)",
+            [],
+        ).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), 0);
+
+        assert_eq!(migrate(&mut conn).unwrap(), MIGRATIONS.len());
+        assert_eq!(schema_version(&conn).unwrap(), MIGRATIONS.len());
+        assert_eq!(
+            table_names(&conn),
+            vec!["draw_offers", "exhibitions", "game_clocks", "game_players", "game_results", "games", "imported_games", "moves", "players", "rating_history", "ratings", "sessions", "share_links"],
+        );
+
+        // The games from before the migration are still there
+        let player_name: String = conn.query_row("SELECT player_name FROM games WHERE id='game'", [], |row| row.get(0)).unwrap();
+        assert_eq!(player_name, "alice");
+
+        let (white_is_bot, black_is_bot): (bool, bool) = conn.query_row("SELECT white_is_bot, black_is_bot FROM games WHERE id='bot game'", [], |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+        assert!(white_is_bot);
+        assert!(!black_is_bot);
+
+        // Migrating an up to date database changes nothing
+        assert_eq!(migrate(&mut conn).unwrap(), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn test_migrate_test_database() {
+        // A copy, test.db itself stays a database from before the migrations
+        let path = std::env::temp_dir().join(format!("krabbi-{}.db", uuid::Uuid::new_v4()));
+        std::fs::copy(concat!(env!("CARGO_MANIFEST_DIR"), "/test.db"), &path).unwrap();
+
+        let mut conn = Connection::open(&path).unwrap();
+        let game_count: u32 = conn.query_row("SELECT COUNT(*) FROM games", [], |row| row.get(0)).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), 0);
+
+        assert_eq!(migrate(&mut conn).unwrap(), MIGRATIONS.len());
+        assert!(table_names(&conn).contains(&"moves".to_string()));
+
+        // Every game is still there, with the defaults of the new columns
+        let (migrated_count, ply): (u32, u32) = conn.query_row("SELECT COUNT(*), MAX(ply) FROM games", [], |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+        assert!(game_count > 0);
+        assert_eq!(migrated_count, game_count);
+        assert_eq!(ply, 0);
+
+        drop(conn);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_migrate_unversioned_tables() {
+        // Databases created just before the migrations already have every table, but no version
+        let mut conn = Connection::open_in_memory().unwrap();
+        for migration in &MIGRATIONS[..2] {
+            conn.execute_batch(migration).unwrap();
+        }
+        conn.execute("INSERT INTO players (name, password_hash) VALUES ('alice', 'hash')", []).unwrap();
+
+        assert_eq!(migrate(&mut conn).unwrap(), MIGRATIONS.len());
+
+        let player_count: u32 = conn.query_row("SELECT COUNT(*) FROM players", [], |row| row.get(0)).unwrap();
+        assert_eq!(player_count, 1);
+    }
+
+    #[test]
+    fn test_migrate_newer_schema() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", MIGRATIONS.len() + 1).unwrap();
+
+        assert!(migrate(&mut conn).is_err());
+    }
+}
@@ -0,0 +1,235 @@
+use std::fmt;
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/*
+    Everything a request can fail with. Every error is answered with its status code,
+    and a JSON body like {"code": "not_your_turn", "message": "Not your turn!"}.
+    Clients should react to the code, the message is only meant for people.
+*/
+#[derive(Debug)]
+pub enum ApiError {
+    // The request is malformed, or has values out of range
+    InvalidRequest(String),
+    InvalidIndex,
+    MissingCoordinate,
+    InvalidBotDifficulty,
+    InvalidColor,
+    EmptyCredentials,
+    InvalidRecord(String),
+
+    // The session token or the credentials are wrong
+    MissingSessionToken,
+    InvalidSessionToken,
+    WrongCredentials,
+
+    // The move breaks the rules of the game
+    IllegalMove(String),
+    NotYourPiece,
+
+    // The player may not do this
+    NotYourTurn,
+    NotYourAccount,
+
+    // Nothing to act on
+    GameNotFound,
+    InviteNotFound,
+    ShareLinkNotFound,
+
+    // The request doesn't fit the current state of the game
+    GameOver,
+    OutOfTime,
+    BotThinking,
+    WaitingForOpponent,
+    GameFull,
+    AlreadyJoined,
+    DrawAlreadyOffered,
+    NoDrawOffered,
+    NameTaken,
+    StaleMove,
+    TooManyExhibitions,
+    IncompleteMoveHistory,
+
+    // Something went wrong on our side
+    Database(rusqlite::Error),
+    DatabaseUnavailable(r2d2::Error),
+    CorruptGame(String),
+    Internal(String),
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+pub struct ErrorResponse {
+    pub code: String,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::InvalidRequest(_) => "invalid_request",
+            ApiError::InvalidIndex => "invalid_index",
+            ApiError::MissingCoordinate => "missing_coordinate",
+            ApiError::InvalidBotDifficulty => "invalid_bot_difficulty",
+            ApiError::InvalidColor => "invalid_color",
+            ApiError::EmptyCredentials => "empty_credentials",
+            ApiError::InvalidRecord(_) => "invalid_record",
+            ApiError::MissingSessionToken => "missing_session_token",
+            ApiError::InvalidSessionToken => "invalid_session_token",
+            ApiError::WrongCredentials => "wrong_credentials",
+            ApiError::IllegalMove(_) => "illegal_move",
+            ApiError::NotYourPiece => "not_your_piece",
+            ApiError::NotYourTurn => "not_your_turn",
+            ApiError::NotYourAccount => "not_your_account",
+            ApiError::GameNotFound => "game_not_found",
+            ApiError::InviteNotFound => "invite_not_found",
+            ApiError::ShareLinkNotFound => "share_link_not_found",
+            ApiError::GameOver => "game_over",
+            ApiError::OutOfTime => "out_of_time",
+            ApiError::BotThinking => "bot_thinking",
+            ApiError::WaitingForOpponent => "waiting_for_opponent",
+            ApiError::GameFull => "game_full",
+            ApiError::AlreadyJoined => "already_joined",
+            ApiError::DrawAlreadyOffered => "draw_already_offered",
+            ApiError::NoDrawOffered => "no_draw_offered",
+            ApiError::NameTaken => "name_taken",
+            ApiError::StaleMove => "stale_move",
+            ApiError::TooManyExhibitions => "too_many_exhibitions",
+            ApiError::IncompleteMoveHistory => "incomplete_move_history",
+            ApiError::Database(_) => "database_error",
+            ApiError::DatabaseUnavailable(_) => "database_unavailable",
+            ApiError::CorruptGame(_) => "corrupt_game",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::InvalidRequest(reason) => write!(f, "Invalid request: {}", reason),
+            ApiError::InvalidIndex => write!(f, "Incorrect index!"),
+            ApiError::MissingCoordinate => write!(f, "Every coordinate of the piece or the move is needed!"),
+            ApiError::InvalidBotDifficulty => write!(f, "Bot difficulty must be 1 or 2!"),
+            ApiError::InvalidColor => write!(f, "Color must be 'w' or 'b'!"),
+            ApiError::EmptyCredentials => write!(f, "Player name and password must not be empty!"),
+            ApiError::InvalidRecord(reason) => write!(f, "Invalid game record: {}", reason),
+            ApiError::MissingSessionToken => write!(f, "Missing session token"),
+            ApiError::InvalidSessionToken => write!(f, "Invalid or expired session token"),
+            ApiError::WrongCredentials => write!(f, "Wrong player name or password!"),
+            ApiError::IllegalMove(reason) => write!(f, "Invalid move: {}", reason),
+            ApiError::NotYourPiece => write!(f, "Not a piece of the current player!"),
+            ApiError::NotYourTurn => write!(f, "Not your turn!"),
+            ApiError::NotYourAccount => write!(f, "Only allowed for your own account!"),
+            ApiError::GameNotFound => write!(f, "No game found"),
+            ApiError::InviteNotFound => write!(f, "No game found for the invite code"),
+            ApiError::ShareLinkNotFound => write!(f, "No share link found, it may have been revoked"),
+            ApiError::GameOver => write!(f, "Game is over!"),
+            ApiError::OutOfTime => write!(f, "Out of time!"),
+            ApiError::BotThinking => write!(f, "Bot is still thinking!"),
+            ApiError::WaitingForOpponent => write!(f, "Waiting for an opponent to join!"),
+            ApiError::GameFull => write!(f, "Game is already full!"),
+            ApiError::AlreadyJoined => write!(f, "You already play in this game!"),
+            ApiError::DrawAlreadyOffered => write!(f, "Draw already offered!"),
+            ApiError::NoDrawOffered => write!(f, "No draw offered!"),
+            ApiError::NameTaken => write!(f, "Player name is already taken!"),
+            ApiError::StaleMove => write!(f, "The game has changed since, reload it!"),
+            ApiError::TooManyExhibitions => write!(f, "Too many exhibitions are being played, wait for one to finish!"),
+            ApiError::IncompleteMoveHistory => write!(f, "The game started before its moves were stored, it can't be exported!"),
+            // The details of internal errors are only logged, they are no business of the client
+            ApiError::Database(_) => write!(f, "Database error"),
+            ApiError::DatabaseUnavailable(_) => write!(f, "Database is busy, try again later"),
+            ApiError::CorruptGame(_) => write!(f, "Stored game could not be read"),
+            ApiError::Internal(_) => write!(f, "Internal error"),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::InvalidRequest(_)
+            | ApiError::InvalidIndex
+            | ApiError::MissingCoordinate
+            | ApiError::InvalidBotDifficulty
+            | ApiError::InvalidColor
+            | ApiError::EmptyCredentials
+            | ApiError::InvalidRecord(_)
+            | ApiError::IllegalMove(_)
+            | ApiError::NotYourPiece => StatusCode::BAD_REQUEST,
+            ApiError::MissingSessionToken
+            | ApiError::InvalidSessionToken
+            | ApiError::WrongCredentials => StatusCode::UNAUTHORIZED,
+            ApiError::NotYourTurn
+            | ApiError::NotYourAccount => StatusCode::FORBIDDEN,
+            ApiError::GameNotFound
+            | ApiError::InviteNotFound
+            | ApiError::ShareLinkNotFound => StatusCode::NOT_FOUND,
+            ApiError::GameOver
+            | ApiError::OutOfTime
+            | ApiError::BotThinking
+            | ApiError::WaitingForOpponent
+            | ApiError::GameFull
+            | ApiError::AlreadyJoined
+            | ApiError::DrawAlreadyOffered
+            | ApiError::NoDrawOffered
+            | ApiError::NameTaken
+            | ApiError::StaleMove
+            | ApiError::TooManyExhibitions
+            | ApiError::IncompleteMoveHistory => StatusCode::CONFLICT,
+            ApiError::Database(_)
+            | ApiError::CorruptGame(_)
+            | ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::DatabaseUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ApiError::Database(err) => println!("Database error: {:?}", err),
+            ApiError::DatabaseUnavailable(err) => println!("No database connection: {}", err),
+            ApiError::CorruptGame(err) | ApiError::Internal(err) => println!("{}: {}", self.code(), err),
+            _ => {},
+        }
+
+        HttpResponse::build(self.status_code()).json(ErrorResponse {
+            code: self.code().to_string(),
+            message: self.to_string(),
+        })
+    }
+}
+
+impl From<rusqlite::Error> for ApiError {
+    fn from(err: rusqlite::Error) -> ApiError {
+        ApiError::Database(err)
+    }
+}
+
+impl From<r2d2::Error> for ApiError {
+    fn from(err: r2d2::Error) -> ApiError {
+        ApiError::DatabaseUnavailable(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::MessageBody;
+
+    #[test]
+    fn test_error_responses() {
+        assert_eq!(ApiError::InvalidIndex.status_code(), StatusCode::BAD_REQUEST);
+        assert_eq!(ApiError::NotYourTurn.status_code(), StatusCode::FORBIDDEN);
+        assert_eq!(ApiError::GameNotFound.status_code(), StatusCode::NOT_FOUND);
+        assert_eq!(ApiError::GameOver.status_code(), StatusCode::CONFLICT);
+        assert_eq!(ApiError::from(rusqlite::Error::InvalidQuery).status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let response = ApiError::IllegalMove("Illegal move!".to_string()).error_response();
+        let body = response.into_body().try_into_bytes().unwrap();
+        let error: ErrorResponse = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(error.code, "illegal_move");
+        assert_eq!(error.message, "Invalid move: Illegal move!");
+    }
+}
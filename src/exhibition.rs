@@ -0,0 +1,134 @@
+use rusqlite::Connection;
+
+use crate::game::engine::{BotPlayer, Engine};
+
+// How long the bots of an exhibition wait before every move, so people can follow the game
+pub const DEFAULT_MOVE_DELAY_MS: u64 = 1_000;
+pub const MAX_MOVE_DELAY_MS: u64 = 60_000;
+// Bots may never make progress, so exhibitions are drawn after this many moves
+pub const MAX_PLIES: u32 = 500;
+// Every move of an exhibition is searched on the server, so only this many can be played at the same time,
+// by everyone, and of the ones a single player started
+pub const MAX_RUNNING_EXHIBITIONS: u32 = 8;
+pub const MAX_RUNNING_EXHIBITIONS_PER_PLAYER: u32 = 2;
+
+/*
+    A game between two bots, played by the server. Anyone who knows its id can watch it.
+*/
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Exhibition {
+    pub white: BotPlayer,
+    pub black: BotPlayer,
+    pub move_delay_ms: u64,
+}
+
+pub fn save_exhibition(conn: &Connection, game_id: &str, exhibition: &Exhibition, started_by: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO exhibitions (game_id, white_engine, white_difficulty, black_engine, black_difficulty, move_delay_ms, started_by)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        rusqlite::params![
+            game_id,
+            exhibition.white.engine.as_str(),
+            exhibition.white.difficulty,
+            exhibition.black.engine.as_str(),
+            exhibition.black.difficulty,
+            exhibition.move_delay_ms,
+            started_by,
+        ],
+    )?;
+
+    Ok(())
+}
+
+/*
+    Returns how many exhibitions are still being played, in total, and of the ones the player started.
+*/
+pub fn count_running_exhibitions(conn: &Connection, player_name: &str) -> rusqlite::Result<(u32, u32)> {
+    conn.query_row(
+        "SELECT COUNT(*), COUNT(CASE WHEN exhibitions.started_by = ?1 THEN 1 END) FROM exhibitions
+            JOIN games ON games.id = exhibitions.game_id
+            WHERE games.winner = 'x'",
+        rusqlite::params![player_name],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )
+}
+
+/*
+    Returns the exhibition, or None if the game isn't one.
+*/
+pub fn load_exhibition(conn: &Connection, game_id: &str) -> rusqlite::Result<Option<Exhibition>> {
+    let result = conn.query_row(
+        "SELECT white_engine, white_difficulty, black_engine, black_difficulty, move_delay_ms FROM exhibitions
+            WHERE game_id=?1",
+        rusqlite::params![game_id],
+        |row| Ok((
+            row.get::<usize, String>(0)?,
+            row.get::<usize, u8>(1)?,
+            row.get::<usize, String>(2)?,
+            row.get::<usize, u8>(3)?,
+            row.get::<usize, u64>(4)?,
+        )),
+    );
+
+    let (white_engine, white_difficulty, black_engine, black_difficulty, move_delay_ms) = match result {
+        Ok(row) => row,
+        Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    let engine = |name: &str, column: usize| Engine::parse(name).ok_or_else(|| {
+        rusqlite::Error::InvalidColumnType(column, name.to_string(), rusqlite::types::Type::Text)
+    });
+
+    Ok(Some(Exhibition {
+        white: BotPlayer { engine: engine(&white_engine, 0)?, difficulty: white_difficulty },
+        black: BotPlayer { engine: engine(&black_engine, 2)?, difficulty: black_difficulty },
+        move_delay_ms,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::migrate;
+
+    #[test]
+    fn test_save_and_load_exhibition() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+
+        let exhibition = Exhibition {
+            white: BotPlayer { engine: Engine::Minimax, difficulty: 3 },
+            black: BotPlayer { engine: Engine::Random, difficulty: 1 },
+            move_delay_ms: 250,
+        };
+        save_exhibition(&conn, "game", &exhibition, "alice").unwrap();
+
+        assert_eq!(load_exhibition(&conn, "game").unwrap(), Some(exhibition));
+        assert_eq!(load_exhibition(&conn, "other game").unwrap(), None);
+    }
+
+    #[test]
+    fn test_count_running_exhibitions() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+
+        let exhibition = Exhibition {
+            white: BotPlayer { engine: Engine::Random, difficulty: 1 },
+            black: BotPlayer { engine: Engine::Random, difficulty: 1 },
+            move_delay_ms: 0,
+        };
+
+        for (game_id, winner, started_by) in [("1", "x", "alice"), ("2", "x", "bob"), ("3", "w", "alice")] {
+            conn.execute(
+                "INSERT INTO games (id, game_state, winner, bot_difficulty) VALUES (?1, '', ?2, 1)",
+                rusqlite::params![game_id, winner],
+            ).unwrap();
+            save_exhibition(&conn, game_id, &exhibition, started_by).unwrap();
+        }
+
+        // Finished exhibitions don't count
+        assert_eq!(count_running_exhibitions(&conn, "alice").unwrap(), (2, 1));
+        assert_eq!(count_running_exhibitions(&conn, "carol").unwrap(), (2, 0));
+    }
+}
@@ -1,10 +1,21 @@
-use self::{legal_moves::is_legal_move, board::Board, board::Color, board::Tile, after_move_eval::after_move_eval, ai::{get_random_move, minimax_best_move}};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use self::{legal_moves::{get_legal_moves, is_legal_move}, board::Board, board::Color, board::Tile, after_move_eval::{after_move_eval, get_captured_coords}, ai::{evaluate, get_random_move}, engine::BotPlayer, notation::{Move, Square}};
 use crate::game::{board::HasColor};
 
 pub mod legal_moves;
 pub mod board;
 pub mod ai;
 pub mod after_move_eval;
+pub mod engine;
+pub mod notation;
+pub mod record;
+
+// Krabbi only plays Copenhagen Hnefatafl, on the 11x11 board
+pub const VARIANT: &str = "copenhagen";
 
 const BLACK_COORDS: [(usize, usize); 24] = [
     (0,3),
@@ -47,6 +58,8 @@ const WHITE_COORDS: [(usize,usize);12] = [
     (7,5),
 ];
 const KING_COORD: (usize,usize) = (5,5);
+// How far behind a bot has to be in its evaluation to accept a draw
+const BOT_DRAW_THRESHOLD: f32 = 5.0;
 /*
     1) create a game with one of the players being AI
 
@@ -63,16 +76,95 @@ const KING_COORD: (usize,usize) = (5,5);
 */
 
 
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EndReason {
+    KingEscaped,
+    EdgeFort,
+    KingCaptured,
+    Surrounded,
+    NoLegalMoves,
+    Timeout,
+    Resignation,
+    DrawAgreed,
+    MoveLimit,
+}
+
+impl EndReason {
+    pub fn as_str(&self) -> &'static str {
+        match &self {
+            EndReason::KingEscaped => "king_escaped",
+            EndReason::EdgeFort => "edge_fort",
+            EndReason::KingCaptured => "king_captured",
+            EndReason::Surrounded => "surrounded",
+            EndReason::NoLegalMoves => "no_legal_moves",
+            EndReason::Timeout => "timeout",
+            EndReason::Resignation => "resignation",
+            EndReason::DrawAgreed => "draw_agreed",
+            EndReason::MoveLimit => "move_limit",
+        }
+    }
+
+    pub fn parse(str: &str) -> Option<EndReason> {
+        match str {
+            "king_escaped" => Some(EndReason::KingEscaped),
+            "edge_fort" => Some(EndReason::EdgeFort),
+            "king_captured" => Some(EndReason::KingCaptured),
+            "surrounded" => Some(EndReason::Surrounded),
+            "no_legal_moves" => Some(EndReason::NoLegalMoves),
+            "timeout" => Some(EndReason::Timeout),
+            "resignation" => Some(EndReason::Resignation),
+            "draw_agreed" => Some(EndReason::DrawAgreed),
+            "move_limit" => Some(EndReason::MoveLimit),
+            _ => None,
+        }
+    }
+}
+
+/*
+    A legal move of the side to move, together with what it would lead to.
+*/
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug, ToSchema)]
+pub struct LegalMove {
+    pub from: (usize, usize),
+    pub to: (usize, usize),
+    // The same move in tafl notation
+    pub notation: Move,
+    pub is_capture: bool,
+    pub reaches_corner: bool,
+}
+
 pub struct Game {
     pub board: Board,
     pub current_player: Color,
     pub bot_white: bool,
     pub bot_black: bool,
     pub bot_difficulty: u8,
+    // The engines of bots that don't play at bot_difficulty, like the bots of exhibition games
+    pub white_engine: Option<BotPlayer>,
+    pub black_engine: Option<BotPlayer>,
+    // Why the game ended, None while it is still going
+    pub end_reason: Option<EndReason>,
+    // The number of moves played, it isn't part of the string format
+    pub ply: u32,
 }
 
 impl Game {
     pub fn new(bot_player_white: bool, bot_player_black: bool, bot_difficulty: u8) -> Game {
+        let mut game = Game::starting_position(bot_player_white, bot_player_black, bot_difficulty);
+
+        if bot_player_black {
+            game.make_opening_move();
+        }
+
+        game
+    }
+
+    /*
+        The position every game starts from, with black to move.
+        Unlike new, this doesn't play the opening move of a bot playing black.
+    */
+    pub fn starting_position(bot_player_white: bool, bot_player_black: bool, bot_difficulty: u8) -> Game {
         let mut new_board = Board::new();
 
         for coord in BLACK_COORDS.iter() {
@@ -83,32 +175,31 @@ impl Game {
         }
         new_board.set_tile(Tile::King, KING_COORD.0, KING_COORD.1);
 
-        if bot_player_black {
-            let ai_move_result = get_random_move(new_board, Color::Black);
-
-            if ai_move_result.is_ok() {
-                let ai_move = ai_move_result.unwrap();
-                new_board.set_tile(Tile::Empty, ai_move[0].0, ai_move[0].1);
-                new_board.set_tile(Tile::Black, ai_move[1].0, ai_move[1].1);
-
-                return Game {
-                    board: new_board,
-                    current_player: Color::White,
-                    bot_white: bot_player_white,
-                    bot_black: bot_player_black,
-                    bot_difficulty: bot_difficulty,
-                };
-            }
-        }
         Game {
             board: new_board,
             current_player: Color::Black,
             bot_white: bot_player_white,
             bot_black: bot_player_black,
             bot_difficulty: bot_difficulty,
+            white_engine: None,
+            black_engine: None,
+            end_reason: None,
+            ply: 0,
         }
     }
 
+    /*
+        Bots playing black open with a random move, right when the game is created.
+        Returns the move played.
+    */
+    pub fn make_opening_move(&mut self) -> Option<[(usize,usize);2]> {
+        let ai_move = get_random_move(self.board, Color::Black).ok()?;
+
+        self.make_move(ai_move[0].0, ai_move[0].1, ai_move[1].0, ai_move[1].1).ok()?;
+
+        Some(ai_move)
+    }
+
     pub fn print_board(&self) {
         self.board.print_board();
         println!("");
@@ -158,6 +249,10 @@ impl Game {
             bot_white: bot_player_white,
             bot_black: bot_player_black,
             bot_difficulty: bot_difficulty,
+            white_engine: None,
+            black_engine: None,
+            end_reason: None,
+            ply: 0,
         })
     }
 
@@ -196,14 +291,58 @@ impl Game {
         self.board.winner 
     }
 
+    /*
+        A game is over once it has a winner, or the players agreed to a draw.
+    */
+    pub fn is_over(&self) -> bool {
+        self.board.winner != Color::None || self.end_reason.is_some()
+    }
+
+    pub fn resign(&mut self, color: Color) {
+        self.board.winner = color.opponent();
+        self.end_reason = Some(EndReason::Resignation);
+    }
+
+    pub fn lose_on_time(&mut self, color: Color) {
+        self.board.winner = color.opponent();
+        self.end_reason = Some(EndReason::Timeout);
+    }
+
+    pub fn agree_draw(&mut self) {
+        self.board.winner = Color::None;
+        self.end_reason = Some(EndReason::DrawAgreed);
+    }
+
+    /*
+        Ends a game that went on for too long, like an exhibition of two bots that never make progress.
+    */
+    pub fn draw_by_move_limit(&mut self) {
+        self.board.winner = Color::None;
+        self.end_reason = Some(EndReason::MoveLimit);
+    }
+
+    /*
+        Bots accept a draw if their evaluation of the position is worse than BOT_DRAW_THRESHOLD.
+        Positive evaluations are good for White. The evaluation of the starting position isn't 0,
+        so positions are compared to it.
+    */
+    pub fn bot_accepts_draw(&self, bot_color: Color) -> bool {
+        let start_evaluation = evaluate(&Game::new(false, false, self.bot_difficulty).board);
+        let evaluation = evaluate(&self.board) - start_evaluation;
+
+        match bot_color {
+            Color::White => evaluation < -BOT_DRAW_THRESHOLD,
+            Color::Black => evaluation > BOT_DRAW_THRESHOLD,
+            Color::None => false,
+        }
+    }
+
     pub fn make_move(&mut self, x_from: usize, y_from: usize, x_to: usize, y_to: usize) -> Result<&Board, &str> {
-        if self.board.winner != Color::None {
+        if self.is_over() {
             return Ok(&self.board);
         }
 
-        println!("Trying to make move {},{} -> {},{}",x_from,y_from,x_to,y_to);
         if !is_legal_move(&self.board, x_from, y_from, x_to, y_to) {
-            println!("Illegal move {},{} -> {},{}",x_from,y_from,x_to,y_to);
             return Err("Illegal move!");
         }
 
@@ -214,46 +353,141 @@ impl Game {
             return Err("Not the current player!");
         }
 
-        self.board.set_tile(*from, x_to, y_to);
-
-        if x_from == 5 && y_from == 5 {
-            self.board.set_tile(Tile::Throne, x_from, y_from);
-        } else {
-            self.board.set_tile(Tile::Empty, x_from, y_from);
-        }
+        self.board = board_after_move(self.board, x_from, y_from, x_to, y_to);
 
-        self.board = after_move_eval(self.board, x_to, y_to);
+        self.end_reason = match self.board.winner {
+            Color::White if self.board.get_tile(x_to, y_to).unwrap() == Tile::King
+                && (x_to == 0 || x_to == 10)
+                && (y_to == 0 || y_to == 10) => Some(EndReason::KingEscaped),
+            Color::White => Some(EndReason::EdgeFort),
+            Color::Black if self.board.board.iter().flatten().any(|tile| *tile == Tile::King) => Some(EndReason::Surrounded),
+            Color::Black => Some(EndReason::KingCaptured),
+            Color::None => None,
+        };
 
         if self.current_player == Color::White {
             self.current_player = Color::Black;
         } else {
             self.current_player = Color::White;
         }
+        self.ply += 1;
+
+        return Ok(&self.board);
+    }
 
-        if (self.current_player == Color::White && self.bot_white) ||
-            (self.current_player == Color::Black && self.bot_black){
-            let ai_move_result = match self.bot_difficulty {
-                1 => get_random_move(self.board, self.current_player),
-                2 => Ok(minimax_best_move(&self.board, self.current_player, 1)),
-                _ => panic!("Invalid bot difficulty"),
-            };
-
-            if ai_move_result.is_err() {
-                self.board.winner = if self.current_player == Color::Black {
-                    Color::White
-                } else {
-                    Color::Black
-                }; 
-                return Ok(&self.board);
+    /*
+        Returns true if the game is still going, and the player to move is a bot.
+        While this is true, the game is waiting on make_bot_move to be called.
+    */
+    pub fn is_bot_turn(&self) -> bool {
+        !self.is_over()
+            && ((self.current_player == Color::White && self.bot_white)
+                || (self.current_player == Color::Black && self.bot_black))
+    }
+
+    /*
+        Returns the bot playing the side, or None if a human plays it.
+    */
+    pub fn bot_player(&self, color: Color) -> Option<BotPlayer> {
+        match color {
+            Color::White if self.bot_white => self.white_engine.or(BotPlayer::from_level(self.bot_difficulty)),
+            Color::Black if self.bot_black => self.black_engine.or(BotPlayer::from_level(self.bot_difficulty)),
+            _ => None,
+        }
+    }
+
+    /*
+        Returns the legal moves of the piece on (x, y), which has to belong to the side to move.
+    */
+    pub fn get_piece_moves(&self, x: usize, y: usize) -> Result<Vec<LegalMove>, String> {
+        if self.board.get_tile(x, y)?.color() != self.current_player {
+            return Err("Not a piece of the current player!".to_string());
+        }
+
+        let moves = get_legal_moves(&self.board, x, y)?
+            .into_iter()
+            .map(|(x_to, y_to)| {
+                let after = board_after_move(self.board, x, y, x_to, y_to);
+
+                LegalMove {
+                    from: (x, y),
+                    to: (x_to, y_to),
+                    notation: Move { from: Square { x, y }, to: Square { x: x_to, y: y_to } },
+                    is_capture: !get_captured_coords(&self.board, &after, self.current_player).is_empty(),
+                    reaches_corner: (x_to == 0 || x_to == 10) && (y_to == 0 || y_to == 10),
+                }
+            })
+            .collect();
+
+        Ok(moves)
+    }
+
+    /*
+        Returns the legal moves of every piece of the side to move. Empty once the game is over.
+    */
+    pub fn get_all_moves(&self) -> Vec<LegalMove> {
+        let mut moves: Vec<LegalMove> = Vec::new();
+
+        if self.is_over() {
+            return moves;
+        }
+
+        for x in 0..=10 {
+            for y in 0..=10 {
+                if let Ok(piece_moves) = self.get_piece_moves(x, y) {
+                    moves.extend(piece_moves);
+                }
             }
+        }
+
+        moves
+    }
 
-            let ai_move = ai_move_result.unwrap();
-            println!("calling make_move from ai_move: {:?}", ai_move);
-            return self.make_move(ai_move[0].0, ai_move[0].1, ai_move[1].0, ai_move[1].1);
+    /*
+        Plays the move of the bot whose turn it is. If the game has a clock, 'budget' is how long
+        the bot may think, otherwise the bot searches as deep as its difficulty allows.
+        Returns the move played, or None if the bot had no legal moves, and lost because of it.
+    */
+    pub fn make_bot_move(&mut self, budget: Option<Duration>) -> Result<Option<[(usize,usize);2]>, &str> {
+        if !self.is_bot_turn() {
+            return Err("Not the bot's turn!");
         }
 
-        return Ok(&self.board);
+        let ai_move_result = match self.bot_player(self.current_player) {
+            Some(bot) => bot.choose_move(&self.board, self.current_player, budget),
+            None => return Err("Invalid bot difficulty"),
+        };
+
+        let ai_move = match ai_move_result {
+            Ok(ai_move) => ai_move,
+            Err(_) => {
+                self.board.winner = self.current_player.opponent();
+                self.end_reason = Some(EndReason::NoLegalMoves);
+                return Ok(None);
+            },
+        };
+
+        self.make_move(ai_move[0].0, ai_move[0].1, ai_move[1].0, ai_move[1].1)?;
+
+        Ok(Some(ai_move))
+    }
+}
+
+/*
+    Moves the piece, and resolves the captures and the win conditions the move triggers.
+    The move has to be legal.
+*/
+fn board_after_move(mut board: Board, x_from: usize, y_from: usize, x_to: usize, y_to: usize) -> Board {
+    let from = board.get_tile(x_from, y_from).unwrap();
+    board.set_tile(from, x_to, y_to);
+
+    if x_from == 5 && y_from == 5 {
+        board.set_tile(Tile::Throne, x_from, y_from);
+    } else {
+        board.set_tile(Tile::Empty, x_from, y_from);
     }
+
+    after_move_eval(board, x_to, y_to)
 }
 
 #[cfg(test)]
@@ -281,4 +515,106 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_make_move_does_not_play_bot_reply() {
+        let mut game = Game::new(true, false, 1);
+
+        assert!(!game.is_bot_turn());
+        assert!(game.make_move(0, 3, 1, 3).is_ok());
+
+        assert_eq!(game.current_player, Color::White);
+        assert!(game.is_bot_turn());
+        assert_eq!(game.board.get_tile(1, 3).unwrap(), Tile::Black);
+        assert_eq!(game.board.get_tile(0, 3).unwrap(), Tile::Empty);
+
+        let bot_move = game.make_bot_move(None).unwrap().unwrap();
+        assert_eq!(game.board.get_tile(bot_move[1].0, bot_move[1].1).unwrap().color(), Color::White);
+
+        assert_eq!(game.current_player, Color::Black);
+        assert!(!game.is_bot_turn());
+        assert!(game.make_bot_move(None).is_err());
+    }
+
+    #[test]
+    fn test_end_reasons() {
+        let mut game = Game::new(false, false, 1);
+        game.board = Board::new();
+        game.board.set_tile(Tile::King, 0, 5);
+        game.board.set_tile(Tile::Black, 5, 0);
+        game.current_player = Color::White;
+
+        assert!(game.make_move(0, 5, 0, 10).is_ok());
+        assert_eq!(game.get_winner(), Color::White);
+        assert_eq!(game.end_reason, Some(EndReason::KingEscaped));
+
+        let mut game = Game::new(false, false, 1);
+        assert!(!game.is_over());
+        game.resign(Color::Black);
+        assert_eq!(game.get_winner(), Color::White);
+        assert_eq!(game.end_reason, Some(EndReason::Resignation));
+
+        let mut game = Game::new(false, false, 1);
+        game.agree_draw();
+        assert!(game.is_over());
+        assert_eq!(game.get_winner(), Color::None);
+        assert!(game.make_move(0, 3, 1, 3).is_ok());
+        assert_eq!(game.board.get_tile(0, 3).unwrap(), Tile::Black);
+
+        for reason in [EndReason::KingEscaped, EndReason::Timeout, EndReason::DrawAgreed, EndReason::MoveLimit] {
+            assert_eq!(EndReason::parse(reason.as_str()), Some(reason));
+        }
+    }
+
+    #[test]
+    fn test_legal_move_flags() {
+        let mut game = Game::new(false, false, 1);
+        game.board = Board::new();
+        game.board.set_tile(Tile::King, 0, 5);
+        game.board.set_tile(Tile::White, 3, 3);
+        game.board.set_tile(Tile::Black, 3, 4);
+        game.board.set_tile(Tile::Black, 9, 9);
+        game.current_player = Color::White;
+
+        let king_moves = game.get_piece_moves(0, 5).unwrap();
+        assert!(king_moves.iter().any(|m| m.to == (0, 10) && m.reaches_corner && !m.is_capture));
+        assert!(king_moves.iter().any(|m| m.to == (0, 6) && !m.reaches_corner));
+
+        // Moving next to the black piece from the other side captures it
+        let white_moves = game.get_piece_moves(3, 3).unwrap();
+        assert!(!white_moves.iter().any(|m| m.is_capture));
+        game.board.set_tile(Tile::White, 4, 5);
+        let white_moves = game.get_piece_moves(4, 5).unwrap();
+        assert!(white_moves.iter().any(|m| m.to == (3, 5) && m.is_capture));
+
+        assert!(game.get_piece_moves(9, 9).is_err());
+        assert!(game.get_piece_moves(5, 5).is_err());
+
+        let all_moves = game.get_all_moves();
+        let piece_move_count: usize = [(0, 5), (3, 3), (4, 5)].iter()
+            .map(|(x, y)| game.get_piece_moves(*x, *y).unwrap().len())
+            .sum();
+        assert_eq!(all_moves.len(), piece_move_count);
+        assert!(all_moves.iter().all(|m| m.from != (9, 9)));
+    }
+
+    #[test]
+    fn test_bot_draw_decision() {
+        let mut game = Game::new(false, false, 2);
+
+        // Nobody is behind at the start
+        assert!(!game.bot_accepts_draw(Color::Black));
+        assert!(!game.bot_accepts_draw(Color::White));
+
+        game.board = Board::new();
+        game.board.set_tile(Tile::King, 5, 5);
+        for i in 1..=9 {
+            game.board.set_tile(Tile::White, 2, i);
+        }
+        game.board.set_tile(Tile::Black, 8, 2);
+
+        // Black is far behind, so the black bot takes the draw, and a white bot wouldn't
+        assert!(game.bot_accepts_draw(Color::Black));
+        assert!(!game.bot_accepts_draw(Color::White));
+    }
 }
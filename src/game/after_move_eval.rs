@@ -43,7 +43,6 @@ pub fn after_move_eval(board: Board, x: usize, y: usize) -> Board {
 
     for coords in shieldwall_captured_coords.iter() {
         if board.get_tile(coords.0, coords.1).unwrap() != Tile::King {
-            println!("Removing at ({},{})", coords.0, coords.1);
             new_board.set_tile(Tile::Empty, coords.0, coords.1);
         }
     }
@@ -101,6 +100,28 @@ pub fn after_move_eval(board: Board, x: usize, y: usize) -> Board {
     new_board
 }
 
+/*
+    Compares the board before and after a move made by 'mover', and returns the coordinates
+    of the opposing pieces that were captured by it.
+*/
+pub fn get_captured_coords(before: &Board, after: &Board, mover: Color) -> Vec<(usize, usize)> {
+    let mut captured: Vec<(usize, usize)> = Vec::new();
+
+    for i in 0..=10 {
+        for j in 0..=10 {
+            let before_color = before.get_tile(i, j).unwrap().color();
+
+            if before_color != Color::None
+                && before_color != mover
+                && after.get_tile(i, j).unwrap() == Tile::Empty {
+                captured.push((i, j));
+            }
+        }
+    }
+
+    captured
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -204,4 +225,24 @@ mod tests {
         assert_eq!(new_board.get_tile(0,2).unwrap(), Tile::Empty);
         assert_eq!(new_board.get_tile(0,3).unwrap(), Tile::White);
     }
+
+    #[test]
+    fn test_get_captured_coords() {
+        let mut board = Board::new();
+        board.set_tile(Tile::Black, 7, 1);
+        board.set_tile(Tile::Black, 5, 3);
+        board.set_tile(Tile::White, 7, 2);
+        board.set_tile(Tile::White, 6, 3);
+        board.set_tile(Tile::White, 7, 4);
+        let before = board;
+        board.set_tile(Tile::Black, 7, 3);
+        let after = after_move_eval(board, 7, 3);
+
+        let captured = get_captured_coords(&before, &after, Color::Black);
+        assert_eq!(captured.len(), 2);
+        assert!(captured.contains(&(7,2)));
+        assert!(captured.contains(&(6,3)));
+
+        assert_eq!(get_captured_coords(&before, &before, Color::Black).len(), 0);
+    }
 }
@@ -1,5 +1,9 @@
 use super::{board::{Board, Color, HasColor, Tile}, legal_moves::{get_legal_moves}, after_move_eval::after_move_eval};
 use rand::seq::SliceRandom; 
+use std::time::{Duration, Instant};
+
+// Roughly how many times longer searching one ply deeper takes
+const BRANCHING_ESTIMATE: u32 = 30;
 
 pub fn get_random_move(board: Board, color: Color) -> Result<[(usize,usize);2], String> {
     let mut all_legal_moves: Vec<[(usize,usize);2]> = Vec::new();
@@ -58,6 +62,13 @@ fn get_all_child_boards(board: &Board, player: Color) -> Vec<Board> {
 }
 
 pub fn minimax_best_move(state: &Board, current_player: Color, depth: i32) -> [(usize,usize);2] {
+    minimax_search(state, current_player, depth).0
+}
+
+/*
+    Returns the best move, together with the evaluation the search found for it. Positive is good for White.
+*/
+pub fn minimax_search(state: &Board, current_player: Color, depth: i32) -> ([(usize,usize);2], f32) {
     let mut piece_coords: Vec<(usize,usize)> = Vec::new();
 
     for i in 0..=10 {
@@ -79,20 +90,10 @@ pub fn minimax_best_move(state: &Board, current_player: Color, depth: i32) -> [(
             .expect("Error in getting legal moves");
 
         if current_tile == Tile::King && current_player == Color::White {
-            if current_legal_moves.contains(&(0,0)) {
-                max_move = [piece.clone(), (0,0)];
-                break;
-            }
-            if current_legal_moves.contains(&(10,0)) {
-                max_move = [piece.clone(), (10,0)];
-                break;
-            }
-            if current_legal_moves.contains(&(0,10)) {
-                max_move = [piece.clone(), (0,10)];
-                break;
-            }
-            if current_legal_moves.contains(&(10,10)) {
-                max_move = [piece.clone(), (10,10)];
+            // Escaping wins, there is nothing left to search
+            if let Some(corner) = [(0,0), (10,0), (0,10), (10,10)].into_iter().find(|corner| current_legal_moves.contains(corner)) {
+                max_move = [piece.clone(), corner];
+                max_value = f32::MAX;
                 break;
             }
         }
@@ -116,10 +117,33 @@ pub fn minimax_best_move(state: &Board, current_player: Color, depth: i32) -> [(
     }
 
     if current_player == Color::White {
-        return max_move;
+        return (max_move, max_value);
     } else {
-        return min_move;
+        return (min_move, min_value);
+    }
+}
+
+/*
+    Iterative deepening on top of minimax_best_move: searches one ply deeper at a time, up to
+    max_depth, as long as the next search is expected to finish within the budget.
+    A search can't be stopped halfway, so the budget can be overrun by the last one.
+*/
+pub fn timed_best_move(state: &Board, current_player: Color, max_depth: i32, budget: Duration) -> [(usize,usize);2] {
+    let start = Instant::now();
+    let mut best_move = minimax_best_move(state, current_player, 0);
+    let mut last_search_time = start.elapsed();
+
+    for depth in 1..=max_depth {
+        if start.elapsed() + (last_search_time * BRANCHING_ESTIMATE) > budget {
+            break;
+        }
+
+        let search_start = Instant::now();
+        best_move = minimax_best_move(state, current_player, depth);
+        last_search_time = search_start.elapsed();
     }
+
+    best_move
 }
 
 // initially, alpha should be f32::MIN, and beta should be f32::MAX
@@ -303,10 +327,5 @@ pub fn evaluate(state: &Board) -> f32 {
             + (king_neighbors * king_neighbors_weight)
             + (king_freedom * king_freedom_weight);
 
-    println!("-----------------------------------");
-    state.print_board();
-    println!("result: {}", result);
-    println!("-----------------------------------");
-
     result
 }
@@ -15,6 +15,16 @@ pub enum Color {
     None,
 }
 
+impl Color {
+    pub fn opponent(&self) -> Color {
+        match &self {
+            Color::Black => Color::White,
+            Color::White => Color::Black,
+            Color::None => Color::None,
+        }
+    }
+}
+
 pub trait HasColor {
     fn color(&self) -> Color;
 }
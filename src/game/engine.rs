@@ -0,0 +1,166 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{board::{Board, Color}, ai::{get_random_move, minimax_best_move, minimax_search, timed_best_move}};
+
+// Deeper searches take too long to be played against
+pub const MAX_MINIMAX_DIFFICULTY: u8 = 3;
+
+// A move, with the evaluation the search found for it
+pub type ScoredMove = ([(usize,usize);2], Option<f32>);
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash, Debug, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Engine {
+    // Plays a random legal move
+    Random,
+    // Searches the moves with minimax, one ply deeper for every level of difficulty above 1
+    Minimax,
+}
+
+impl Engine {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Engine::Random => "random",
+            Engine::Minimax => "minimax",
+        }
+    }
+
+    pub fn parse(str: &str) -> Option<Engine> {
+        match str {
+            "random" => Some(Engine::Random),
+            "minimax" => Some(Engine::Minimax),
+            _ => None,
+        }
+    }
+}
+
+/*
+    The engine a bot plays with, and how strong it plays. The random engine only has difficulty 1.
+*/
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash, Debug, ToSchema)]
+pub struct BotPlayer {
+    pub engine: Engine,
+    pub difficulty: u8,
+}
+
+impl BotPlayer {
+    /*
+        The bots of the bot_difficulty of games: 1 plays random moves, 2 searches two plies deep.
+    */
+    pub fn from_level(level: u8) -> Option<BotPlayer> {
+        match level {
+            1 => Some(BotPlayer { engine: Engine::Random, difficulty: 1 }),
+            2 => Some(BotPlayer { engine: Engine::Minimax, difficulty: 2 }),
+            _ => None,
+        }
+    }
+
+    pub fn level(&self) -> Option<u8> {
+        (1..=2).find(|level| BotPlayer::from_level(*level) == Some(*self))
+    }
+
+    pub fn is_valid(&self) -> bool {
+        match self.engine {
+            Engine::Random => self.difficulty == 1,
+            Engine::Minimax => (1..=MAX_MINIMAX_DIFFICULTY).contains(&self.difficulty),
+        }
+    }
+
+    /*
+        Chooses the move of 'color'. With a budget, minimax only searches as deep as the budget allows.
+    */
+    pub fn choose_move(&self, board: &Board, color: Color, budget: Option<Duration>) -> Result<[(usize,usize);2], String> {
+        let depth = i32::from(self.difficulty) - 1;
+
+        match (self.engine, budget) {
+            (Engine::Random, _) => get_random_move(*board, color),
+            // Minimax has no move to return without legal moves
+            (Engine::Minimax, _) if get_random_move(*board, color).is_err() => Err("No legal moves".to_string()),
+            (Engine::Minimax, None) => Ok(minimax_best_move(board, color, depth)),
+            (Engine::Minimax, Some(budget)) => Ok(timed_best_move(board, color, depth, budget)),
+        }
+    }
+
+    /*
+        Chooses the move of 'color' like choose_move without a budget, together with the evaluation
+        the search found for it, positive is good for White. The random engine doesn't search, so it has none.
+    */
+    pub fn search(&self, board: &Board, color: Color) -> Result<ScoredMove, String> {
+        match self.engine {
+            Engine::Random => Ok((get_random_move(*board, color)?, None)),
+            Engine::Minimax if get_random_move(*board, color).is_err() => Err("No legal moves".to_string()),
+            Engine::Minimax => {
+                let (best_move, score) = minimax_search(board, color, i32::from(self.difficulty) - 1);
+                Ok((best_move, Some(score)))
+            },
+        }
+    }
+}
+
+/*
+    Parses a bot like "minimax:2", the engine and its difficulty.
+*/
+pub fn parse_bot_player(str: &str) -> Result<BotPlayer, String> {
+    let (engine, difficulty) = str.split_once(':')
+        .ok_or_else(|| format!("'{}' isn't an engine and a difficulty, like minimax:2", str))?;
+
+    let bot = BotPlayer {
+        engine: Engine::parse(engine).ok_or_else(|| format!("Unknown engine '{}'", engine))?,
+        difficulty: difficulty.parse().map_err(|_| format!("Invalid difficulty '{}'", difficulty))?,
+    };
+
+    if !bot.is_valid() {
+        return Err(format!("The {} engine has no difficulty {}", engine, difficulty));
+    }
+
+    Ok(bot)
+}
+
+/*
+    Parses the value that follows a command line flag, like the number after --games.
+*/
+pub fn parse_value<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("{} needs a value", flag))?;
+    value.parse().map_err(|_| format!("Invalid value for {}: '{}'", flag, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bot_players() {
+        for level in 1..=2 {
+            let bot = BotPlayer::from_level(level).unwrap();
+            assert!(bot.is_valid());
+            assert_eq!(bot.level(), Some(level));
+        }
+        assert_eq!(BotPlayer::from_level(3), None);
+
+        let deep = BotPlayer { engine: Engine::Minimax, difficulty: 3 };
+        assert!(deep.is_valid());
+        assert_eq!(deep.level(), None);
+        assert!(!BotPlayer { engine: Engine::Minimax, difficulty: MAX_MINIMAX_DIFFICULTY + 1 }.is_valid());
+        assert!(!BotPlayer { engine: Engine::Random, difficulty: 2 }.is_valid());
+
+        assert_eq!(Engine::parse(Engine::Minimax.as_str()), Some(Engine::Minimax));
+    }
+
+    #[test]
+    fn test_parse_bot_player() {
+        assert_eq!(parse_bot_player("minimax:3"), Ok(BotPlayer { engine: Engine::Minimax, difficulty: 3 }));
+        assert!(parse_bot_player("random:2").is_err());
+        assert!(parse_bot_player("minimax").is_err());
+        assert!(parse_bot_player("alphazero:1").is_err());
+    }
+
+    #[test]
+    fn test_parse_value() {
+        assert_eq!(parse_value::<u32>("--games", Some("12".to_string())), Ok(12));
+        assert!(parse_value::<u32>("--games", Some("many".to_string())).is_err());
+        assert!(parse_value::<u32>("--games", None).is_err());
+    }
+}
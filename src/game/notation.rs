@@ -0,0 +1,184 @@
+use std::{borrow::Cow, fmt, str::FromStr};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
+use utoipa::{
+    PartialSchema, ToSchema,
+    openapi::{ObjectBuilder, RefOr, Schema, schema::Type},
+};
+
+/*
+    A square of the board in tafl notation: columns a to k from the left, rows 1 to 11 from the bottom.
+    Board.board[x][y] is row x from the top and column y from the left, so a11 is (0, 0) and k1 is (10, 10).
+*/
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Square {
+    pub x: usize,
+    pub y: usize,
+}
+
+impl Square {
+    pub fn new(x: usize, y: usize) -> Option<Square> {
+        (x <= 10 && y <= 10).then_some(Square { x, y })
+    }
+
+    pub fn from_coords(coords: (usize, usize)) -> Option<Square> {
+        Square::new(coords.0, coords.1)
+    }
+
+    pub fn coords(&self) -> (usize, usize) {
+        (self.x, self.y)
+    }
+}
+
+impl fmt::Display for Square {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", char::from(b'a' + self.y as u8), 11 - self.x)
+    }
+}
+
+impl FromStr for Square {
+    type Err = String;
+
+    fn from_str(str: &str) -> Result<Square, String> {
+        let mut chars = str.chars();
+        let column = chars.next().map(|column| column.to_ascii_lowercase());
+        let row = chars.as_str();
+        // parse would also take "+1" and "01"
+        let row: Option<usize> = if row.bytes().all(|byte| byte.is_ascii_digit()) && !row.starts_with('0') {
+            row.parse().ok()
+        } else {
+            None
+        };
+
+        match (column, row) {
+            (Some(column @ 'a'..='k'), Some(row @ 1..=11)) => Ok(Square { x: 11 - row, y: column as usize - 'a' as usize }),
+            _ => Err(format!("'{}' isn't a square from a1 to k11", str)),
+        }
+    }
+}
+
+/*
+    A move from one square to another, written like "d1-d4".
+*/
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Move {
+    pub from: Square,
+    pub to: Square,
+}
+
+impl Move {
+    pub fn from_coords(from: (usize, usize), to: (usize, usize)) -> Option<Move> {
+        Some(Move { from: Square::from_coords(from)?, to: Square::from_coords(to)? })
+    }
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.from, self.to)
+    }
+}
+
+impl FromStr for Move {
+    type Err = String;
+
+    fn from_str(str: &str) -> Result<Move, String> {
+        let (from, to) = str.split_once('-').ok_or_else(|| format!("'{}' isn't a move like d1-d4", str))?;
+
+        Ok(Move { from: from.parse()?, to: to.parse()? })
+    }
+}
+
+// Both are sent as their notation, the same as they are written
+
+impl Serialize for Square {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Square {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Square, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for Move {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Move {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Move, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+impl PartialSchema for Square {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .schema_type(Type::String)
+            .description(Some("A square from a1 to k11, columns from the left and rows from the bottom"))
+            .examples(["d4"])
+            .into()
+    }
+}
+
+impl ToSchema for Square {
+    fn name() -> Cow<'static, str> {
+        Cow::Borrowed("Square")
+    }
+}
+
+impl PartialSchema for Move {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .schema_type(Type::String)
+            .description(Some("A move from one square to another"))
+            .examples(["d1-d4"])
+            .into()
+    }
+}
+
+impl ToSchema for Move {
+    fn name() -> Cow<'static, str> {
+        Cow::Borrowed("Move")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_squares() {
+        assert_eq!("a11".parse(), Ok(Square { x: 0, y: 0 }));
+        assert_eq!("k1".parse(), Ok(Square { x: 10, y: 10 }));
+        assert_eq!("F6".parse(), Ok(Square { x: 5, y: 5 }));
+
+        for x in 0..=10 {
+            for y in 0..=10 {
+                let square = Square::new(x, y).unwrap();
+                assert_eq!(square.to_string().parse(), Ok(square));
+            }
+        }
+
+        for invalid in ["", "a", "a0", "a12", "l1", "1a", "a+1", "a01", "a1x"] {
+            assert!(invalid.parse::<Square>().is_err(), "{}", invalid);
+        }
+        assert_eq!(Square::new(11, 0), None);
+    }
+
+    #[test]
+    fn test_moves() {
+        let move_played: Move = "d1-d4".parse().unwrap();
+        assert_eq!(move_played, Move::from_coords((10, 3), (7, 3)).unwrap());
+        assert_eq!(move_played.to_string(), "d1-d4");
+        assert!("d1d4".parse::<Move>().is_err());
+        assert!("d1-z4".parse::<Move>().is_err());
+
+        assert_eq!(serde_json::to_string(&move_played).unwrap(), "\"d1-d4\"");
+        assert_eq!(serde_json::from_str::<Move>("\"d1-d4\"").unwrap(), move_played);
+        assert!(serde_json::from_str::<Move>("\"d1\"").is_err());
+    }
+}
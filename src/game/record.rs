@@ -0,0 +1,429 @@
+use std::fmt;
+
+use super::{
+    EndReason, Game, VARIANT,
+    after_move_eval::get_captured_coords,
+    board::Color,
+    notation::{Move, Square},
+};
+
+// The pieces every move of a game captured
+pub type GameCaptures = Vec<Vec<(usize, usize)>>;
+
+/*
+    How a recorded game ended, or that it hasn't yet.
+*/
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RecordResult {
+    White,
+    Black,
+    Draw,
+    Ongoing,
+}
+
+impl RecordResult {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RecordResult::White => "white",
+            RecordResult::Black => "black",
+            RecordResult::Draw => "draw",
+            RecordResult::Ongoing => "ongoing",
+        }
+    }
+
+    pub fn parse(str: &str) -> Option<RecordResult> {
+        match str {
+            "white" => Some(RecordResult::White),
+            "black" => Some(RecordResult::Black),
+            "draw" => Some(RecordResult::Draw),
+            "ongoing" => Some(RecordResult::Ongoing),
+            _ => None,
+        }
+    }
+
+    pub fn of_game(game: &Game) -> RecordResult {
+        match (game.get_winner(), game.is_over()) {
+            (Color::White, _) => RecordResult::White,
+            (Color::Black, _) => RecordResult::Black,
+            (_, true) => RecordResult::Draw,
+            (_, false) => RecordResult::Ongoing,
+        }
+    }
+}
+
+/*
+    A move of a record, with the pieces it captured.
+*/
+#[derive(Clone, PartialEq, Debug)]
+pub struct RecordMove {
+    pub played: Move,
+    pub captures: Vec<Square>,
+}
+
+impl fmt::Display for RecordMove {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.played)?;
+
+        if !self.captures.is_empty() {
+            let captures: Vec<String> = self.captures.iter().map(Square::to_string).collect();
+            write!(f, "x{}", captures.join("/"))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl RecordMove {
+    /*
+        Parses a move like "d1-d4", or "d1-d4xd5/c4" for a move that captured the pieces on d5 and c4.
+    */
+    pub fn parse(str: &str) -> Result<RecordMove, String> {
+        let (played, captures) = match str.split_once('x') {
+            Some((played, captures)) => (played, captures.split('/').map(str::parse).collect::<Result<Vec<Square>, String>>()?),
+            None => (str, Vec::new()),
+        };
+
+        Ok(RecordMove { played: played.parse()?, captures })
+    }
+}
+
+/*
+    A complete game as text, in the style of OpenTafl's .otg records: a header per line, like
+    "[white:alice]", followed by the numbered moves in tafl notation. Black moves first, so every
+    numbered pair is Black's move and White's reply:
+
+        [variant:copenhagen]
+        [white:alice]
+        [black:Krabbi bot 2]
+        [date:2026-10-19]
+        [result:white]
+        [termination:king_escaped]
+
+        1. d1-d4 f8-f9
+        2. a4-c4xc5 ...
+
+    Unknown headers are ignored, so records of other tools can be read as long as their moves are.
+*/
+#[derive(Clone, PartialEq, Debug)]
+pub struct GameRecord {
+    pub white: Option<String>,
+    pub black: Option<String>,
+    pub date: Option<String>,
+    pub result: RecordResult,
+    // Why the game ended, None while it is still going
+    pub termination: Option<EndReason>,
+    pub moves: Vec<RecordMove>,
+}
+
+impl fmt::Display for GameRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "[variant:{}]", VARIANT)?;
+
+        for (key, value) in [("white", &self.white), ("black", &self.black), ("date", &self.date)] {
+            if let Some(value) = value {
+                writeln!(f, "[{}:{}]", key, escape_header(value))?;
+            }
+        }
+
+        writeln!(f, "[result:{}]", self.result.as_str())?;
+
+        if let Some(termination) = self.termination {
+            writeln!(f, "[termination:{}]", termination.as_str())?;
+        }
+
+        writeln!(f)?;
+
+        for (index, pair) in self.moves.chunks(2).enumerate() {
+            let pair: Vec<String> = pair.iter().map(RecordMove::to_string).collect();
+            writeln!(f, "{}. {}", index + 1, pair.join(" "))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl GameRecord {
+    pub fn parse(str: &str) -> Result<GameRecord, String> {
+        let mut record = GameRecord {
+            white: None,
+            black: None,
+            date: None,
+            result: RecordResult::Ongoing,
+            termination: None,
+            moves: Vec::new(),
+        };
+
+        for line in str.lines().map(str::trim) {
+            if let Some(header) = line.strip_prefix('[') {
+                let (key, value) = header.strip_suffix(']')
+                    .and_then(|header| header.split_once(':'))
+                    .ok_or_else(|| format!("'{}' isn't a header like [key:value]", line))?;
+                let value = unescape_header(value.trim()).ok_or_else(|| format!("'{}' ends with a lone backslash", line))?;
+                let value = value.as_str();
+
+                match key.trim() {
+                    "variant" if !value.eq_ignore_ascii_case(VARIANT) => return Err(format!("Unknown variant '{}'", value)),
+                    "white" => record.white = Some(value.to_string()),
+                    "black" => record.black = Some(value.to_string()),
+                    "date" => record.date = Some(value.to_string()),
+                    "result" => record.result = RecordResult::parse(value).ok_or_else(|| format!("Unknown result '{}'", value))?,
+                    "termination" => record.termination = Some(EndReason::parse(value).ok_or_else(|| format!("Unknown termination '{}'", value))?),
+                    _ => {},
+                }
+
+                continue;
+            }
+
+            for token in line.split_whitespace() {
+                // Move numbers
+                if token.strip_suffix('.').is_some_and(|number| number.parse::<u32>().is_ok()) {
+                    continue;
+                }
+
+                record.moves.push(RecordMove::parse(token)?);
+            }
+        }
+
+        Ok(record)
+    }
+
+    /*
+        Plays the moves from the starting position, and returns the game they lead to, with the record's result.
+        Every move has to be legal, and its captures, if the record has them, have to be the ones it made.
+        Also returns the pieces every move captured.
+    */
+    pub fn replay(&self) -> Result<(Game, GameCaptures), String> {
+        let mut game = Game::starting_position(false, false, 1);
+        let mut captures = Vec::new();
+
+        for (index, record_move) in self.moves.iter().enumerate() {
+            let invalid = |reason: &str| format!("Move {} ({}): {}", index + 1, record_move, reason);
+
+            if game.is_over() {
+                return Err(invalid("the game was already over"));
+            }
+
+            let board_before = game.board;
+            let mover = game.current_player;
+            let (from, to) = (record_move.played.from, record_move.played.to);
+
+            game.make_move(from.x, from.y, to.x, to.y).map_err(invalid)?;
+
+            let mut captured = get_captured_coords(&board_before, &game.board, mover);
+            captured.sort();
+
+            let mut recorded: Vec<(usize, usize)> = record_move.captures.iter().map(Square::coords).collect();
+            recorded.sort();
+
+            if !record_move.captures.is_empty() && recorded != captured {
+                return Err(invalid("it doesn't capture these pieces"));
+            }
+
+            captures.push(captured);
+        }
+
+        let result = RecordResult::of_game(&game);
+
+        match (result, self.result) {
+            // The moves ended the game, and the record has to agree
+            (RecordResult::Ongoing, _) => {},
+            (result, recorded) if result == recorded => {
+                return match (self.termination, game.end_reason) {
+                    (Some(termination), Some(end_reason)) if termination != end_reason => {
+                        Err(format!("The moves end by {}, not {}", end_reason.as_str(), termination.as_str()))
+                    },
+                    _ => Ok((game, captures)),
+                };
+            },
+            _ => return Err(format!("The moves end in a {} result, not {}", result.as_str(), self.result.as_str())),
+        }
+
+        // Otherwise the game ended off the board
+        match (self.result, self.termination) {
+            (RecordResult::Ongoing, None) => {},
+            (RecordResult::Ongoing, Some(termination)) => {
+                return Err(format!("A game that is still going can't have ended by {}", termination.as_str()));
+            },
+            (RecordResult::Draw, Some(EndReason::MoveLimit)) => game.draw_by_move_limit(),
+            (RecordResult::Draw, None | Some(EndReason::DrawAgreed)) => game.agree_draw(),
+            (RecordResult::White | RecordResult::Black, None | Some(EndReason::Resignation)) => {
+                game.resign(if self.result == RecordResult::White { Color::Black } else { Color::White });
+            },
+            (RecordResult::White | RecordResult::Black, Some(EndReason::Timeout)) => {
+                game.lose_on_time(if self.result == RecordResult::White { Color::Black } else { Color::White });
+            },
+            (_, Some(termination)) => {
+                return Err(format!("A {} result can't come from {} after these moves", self.result.as_str(), termination.as_str()));
+            },
+        }
+
+        Ok((game, captures))
+    }
+}
+
+/*
+    Header values are written with a backslash before backslashes, brackets and quotes, and line breaks
+    as \n and \r, so that names can't end their header early, or start another one.
+*/
+fn escape_header(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for char in value.chars() {
+        match char {
+            '\\' | '[' | ']' | '"' => {
+                escaped.push('\\');
+                escaped.push(char);
+            },
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            _ => escaped.push(char),
+        }
+    }
+
+    escaped
+}
+
+/*
+    The opposite of escape_header. Returns None for a value that ends with a lone backslash.
+*/
+fn unescape_header(value: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(char) = chars.next() {
+        if char != '\\' {
+            unescaped.push(char);
+            continue;
+        }
+
+        match chars.next()? {
+            'n' => unescaped.push('\n'),
+            'r' => unescaped.push('\r'),
+            escaped => unescaped.push(escaped),
+        }
+    }
+
+    Some(unescaped)
+}
+
+/*
+    Formats a time in milliseconds since the Unix epoch as a UTC date like 2026-10-19.
+*/
+pub fn format_date(ms: u64) -> String {
+    // Howard Hinnant's days to civil date algorithm
+    let days = (ms / 86_400_000) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(game: &mut Game, moves: &[&str]) -> Vec<RecordMove> {
+        moves.iter().map(|played| {
+            let played: Move = played.parse().unwrap();
+            let board_before = game.board;
+            let mover = game.current_player;
+            game.make_move(played.from.x, played.from.y, played.to.x, played.to.y).unwrap();
+
+            let captures = get_captured_coords(&board_before, &game.board, mover).into_iter()
+                .map(|coords| Square::from_coords(coords).unwrap())
+                .collect();
+            RecordMove { played, captures }
+        }).collect()
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        let mut game = Game::starting_position(false, false, 1);
+        let moves = play(&mut game, &["d1-d4", "f8-f9", "a4-a3"]);
+
+        let record = GameRecord {
+            white: Some("alice".to_string()),
+            black: Some("Krabbi bot 2".to_string()),
+            date: Some("2026-10-19".to_string()),
+            result: RecordResult::Black,
+            termination: Some(EndReason::Resignation),
+            moves,
+        };
+        let text = record.to_string();
+        assert!(text.contains("[black:Krabbi bot 2]\n"));
+        assert!(text.contains("\n1. d1-d4 f8-f9\n2. a4-a3\n"));
+
+        assert_eq!(GameRecord::parse(&text).unwrap(), record);
+
+        let (replayed, captures) = record.replay().unwrap();
+        assert_eq!(replayed.board.to_string(), game.board.to_string());
+        assert_eq!(replayed.get_winner(), Color::Black);
+        assert_eq!(replayed.end_reason, Some(EndReason::Resignation));
+        assert_eq!(captures.len(), 3);
+    }
+
+    #[test]
+    fn test_record_header_escaping() {
+        let name = "mallory]\n[result:white] \"\\";
+        let record = GameRecord {
+            white: Some(name.to_string()),
+            black: None,
+            date: None,
+            result: RecordResult::Ongoing,
+            termination: None,
+            moves: Vec::new(),
+        };
+        let text = record.to_string();
+
+        // The name stays on its header's line, and doesn't end it
+        assert!(text.starts_with("[variant:copenhagen]\n[white:mallory\\]\\n\\[result:white\\] \\\"\\\\]\n"));
+        assert_eq!(GameRecord::parse(&text).unwrap(), record);
+        assert!(GameRecord::parse("[white:mallory\\]\n").is_err());
+    }
+
+    #[test]
+    fn test_record_moves() {
+        assert_eq!(RecordMove::parse("a4-c4xc5/d4").unwrap().to_string(), "a4-c4xc5/d4");
+        assert!(RecordMove::parse("a4-c4x").is_err());
+
+        // Unknown headers and move numbers are skipped
+        let record = GameRecord::parse("[rules:dim:11]\n[result:ongoing]\n1. d1-d4\n").unwrap();
+        assert_eq!(record.moves.len(), 1);
+        assert!(GameRecord::parse("[variant:brandubh]\n").is_err());
+        assert!(GameRecord::parse("[result:1-0]\n").is_err());
+    }
+
+    #[test]
+    fn test_replay_checks_moves() {
+        // White can't move first
+        assert!(GameRecord::parse("1. f8-f9").unwrap().replay().is_err());
+        // d1-d4 captures nothing
+        assert!(GameRecord::parse("1. d1-d4xe4").unwrap().replay().is_err());
+        // A draw needs a reason that fits the moves
+        assert!(GameRecord::parse("[result:draw]\n[termination:king_escaped]\n1. d1-d4").unwrap().replay().is_err());
+        // A game that is still going has no termination
+        assert!(GameRecord::parse("[result:ongoing]\n[termination:resignation]\n1. d1-d4").unwrap().replay().is_err());
+
+        let (game, _) = GameRecord::parse("[result:draw]\n1. d1-d4").unwrap().replay().unwrap();
+        assert_eq!(game.end_reason, Some(EndReason::DrawAgreed));
+
+        // The king escapes to a11, so the record can't say the game ended on time
+        let escape = "1. a4-b4 f8-i8\n2. b4-a4 f7-f9\n3. a4-b4 f6-f8\n4. b4-a4 f8-b8\n5. a4-b4 b8-b11\n6. b4-a4 b11-a11";
+        let (game, _) = GameRecord::parse(&format!("[result:white]\n[termination:king_escaped]\n{}", escape)).unwrap().replay().unwrap();
+        assert_eq!(game.end_reason, Some(EndReason::KingEscaped));
+        assert!(GameRecord::parse(&format!("[result:white]\n[termination:timeout]\n{}", escape)).unwrap().replay().is_err());
+    }
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(951_782_400_000), "2000-02-29");
+        assert_eq!(format_date(1_792_368_000_000), "2026-10-19");
+    }
+}
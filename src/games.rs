@@ -0,0 +1,255 @@
+use rusqlite::Connection;
+
+use crate::{
+    clock::{Clock, now_ms},
+    error::ApiError,
+    exhibition::load_exhibition,
+    game::{EndReason, Game, board::Color},
+    live::{GameEvent, GameUpdate},
+    rating,
+};
+
+pub fn color_to_string(color: Color) -> String {
+    match color {
+        Color::White => "w".to_string(),
+        Color::Black => "b".to_string(),
+        Color::None => "x".to_string(),
+    }
+}
+
+pub fn string_to_color(str: &str) -> Color {
+    match str {
+        "w" => Color::White,
+        "b" => Color::Black,
+        _ => Color::None,
+    }
+}
+
+/*
+    The value of the games table's winner column: the winner's color, 'd' for a draw,
+    and 'x' while the game is still going.
+*/
+pub fn result_to_string(game: &Game) -> String {
+    if game.is_over() && game.get_winner() == Color::None {
+        "d".to_string()
+    } else {
+        color_to_string(game.get_winner())
+    }
+}
+
+/*
+    Loads a game, together with its result, which isn't part of the stored FEN.
+*/
+pub fn load_game(conn: &Connection, game_id: &str) -> Result<Game, ApiError> {
+    let row_result = conn.query_row(
+        "SELECT game_state, winner, bot_difficulty, reason, ply FROM games
+            LEFT JOIN game_results ON game_results.game_id = games.id
+            WHERE id=?1",
+        rusqlite::params![game_id],
+        |row| Ok((
+            row.get::<&str, String>("game_state")?,
+            row.get::<&str, String>("winner")?,
+            row.get::<&str, u8>("bot_difficulty")?,
+            row.get::<&str, Option<String>>("reason")?,
+            row.get::<&str, u32>("ply")?,
+        )),
+    );
+
+    let (chfen, winner, difficulty, reason, ply) = match row_result {
+        Ok(row) => row,
+        Err(rusqlite::Error::QueryReturnedNoRows) => return Err(ApiError::GameNotFound),
+        Err(err) => return Err(ApiError::Database(err)),
+    };
+
+    let mut game = match Game::from_string(chfen, difficulty) {
+        Ok(game) => game,
+        Err(err) => return Err(ApiError::CorruptGame(err)),
+    };
+    game.board.winner = string_to_color(&winner);
+    game.end_reason = reason.as_deref().and_then(EndReason::parse);
+    game.ply = ply;
+
+    // Draws stored before the reason of a result was, were all agreed
+    if winner == "d" && game.end_reason.is_none() {
+        game.agree_draw();
+    }
+
+    if let Some(exhibition) = load_exhibition(conn, game_id)? {
+        game.white_engine = Some(exhibition.white);
+        game.black_engine = Some(exhibition.black);
+    }
+
+    Ok(game)
+}
+
+/*
+    Stores the new state of a game after a move. The game must have been at ply_before, and still going,
+    when it was loaded; if another request changed it since, nothing is stored and StaleMove is returned.
+*/
+pub fn update_game_state(conn: &Connection, game_id: &str, game: &Game, ply_before: u32) -> Result<(), ApiError> {
+    let game_state = game.to_string().map_err(ApiError::Internal)?;
+
+    let updated = conn.execute(
+        "UPDATE games SET game_state=?1, winner=?2, ply=?3, updated_at=?4 WHERE id=?5 AND ply=?6 AND winner='x'",
+        rusqlite::params![game_state, result_to_string(game), game.ply, now_ms(), game_id, ply_before],
+    )?;
+
+    if updated == 0 {
+        return Err(ApiError::StaleMove);
+    }
+
+    Ok(())
+}
+
+/*
+    Returns the name the given side of a game is rated under, bots are rated per engine and difficulty.
+*/
+pub fn get_rated_name(conn: &Connection, game_id: &str, game: &Game, color: Color) -> rusqlite::Result<Option<String>> {
+    if let Some(bot) = game.bot_player(color) {
+        return Ok(Some(rating::engine_player_name(&bot)));
+    }
+
+    get_seat_player(conn, game_id, color)
+}
+
+/*
+    Stores why the game ended and rates it. The winner is already stored, with the move that ended the game
+    by update_game_state, or by end_game for games that ended off the board.
+    Pending draw offers are dropped, there is nothing left to agree on.
+*/
+pub fn save_result(conn: &Connection, game_id: &str, game: &Game) -> rusqlite::Result<()> {
+    if let Some(reason) = game.end_reason {
+        conn.execute(
+            "INSERT OR REPLACE INTO game_results (game_id, reason) VALUES (?1, ?2)",
+            rusqlite::params![game_id, reason.as_str()],
+        )?;
+    }
+
+    if game.is_over() {
+        let white = get_rated_name(conn, game_id, game, Color::White)?;
+        let black = get_rated_name(conn, game_id, game, Color::Black)?;
+
+        // Games that ended before an opponent joined aren't rated
+        if let (Some(white), Some(black)) = (white, black) {
+            rating::rate_game(conn, game_id, &white, &black, game.get_winner(), now_ms())?;
+        }
+    }
+
+    clear_draw_offer(conn, game_id)
+}
+
+/*
+    Returns the side that offered a draw in the game, if there is an open offer.
+*/
+pub fn get_draw_offer(conn: &Connection, game_id: &str) -> rusqlite::Result<Option<Color>> {
+    let result = conn.query_row(
+        "SELECT color FROM draw_offers WHERE game_id=?1",
+        rusqlite::params![game_id],
+        |row| row.get::<usize, String>(0),
+    );
+
+    match result {
+        Ok(color) => Ok(Some(string_to_color(&color))),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+pub fn clear_draw_offer(conn: &Connection, game_id: &str) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM draw_offers WHERE game_id=?1", rusqlite::params![game_id])?;
+    Ok(())
+}
+
+/*
+    Stores the result of a game that ended off the board, by resignation, a draw or the clock, and saves it.
+    Only a game that is still going at the ply it was loaded at is ended, so a result can't overwrite
+    one that was stored meanwhile. Returns false if the game moved on or ended in between.
+*/
+pub fn end_game(conn: &Connection, game_id: &str, game: &Game) -> rusqlite::Result<bool> {
+    let updated = conn.execute(
+        "UPDATE games SET winner=?1, updated_at=?2 WHERE id=?3 AND winner='x' AND ply=?4",
+        rusqlite::params![result_to_string(game), now_ms(), game_id, game.ply],
+    )?;
+
+    if updated == 0 {
+        return Ok(false);
+    }
+
+    save_result(conn, game_id, game)?;
+
+    Ok(true)
+}
+
+/*
+    Ends the game on time if the side to move has run out of it, and returns true if it did.
+    Flags are checked whenever a game is loaded, so a player who stops moving loses
+    as soon as anyone looks at the game.
+*/
+pub fn check_flag(conn: &Connection, game_id: &str, game: &mut Game, clock: &Option<Clock>, now: u64) -> rusqlite::Result<bool> {
+    let clock = match clock {
+        Some(clock) => clock,
+        None => return Ok(false),
+    };
+
+    if game.is_over() || !clock.is_flagged(game.current_player, now) {
+        return Ok(false);
+    }
+
+    game.lose_on_time(game.current_player);
+
+    end_game(conn, game_id, game)
+}
+
+pub fn state_update(game: &Game, clock: Option<Clock>, event: GameEvent) -> GameUpdate {
+    GameUpdate {
+        event,
+        fen: game.to_string().unwrap(),
+        ply: game.ply,
+        winner: result_to_string(game),
+        bot_thinking: game.is_bot_turn(),
+        from: None,
+        to: None,
+        notation: None,
+        captures: Vec::new(),
+        clock,
+        result_reason: game.end_reason,
+        draw_offered_by: None,
+    }
+}
+
+/*
+    Returns the name of the player playing the given side of a game, or None if the seat is
+    still waiting for someone to join. Games created before players had seats only have the
+    single player_name of the games table, who plays every side that isn't a bot.
+*/
+pub fn get_seat_player(conn: &Connection, game_id: &str, color: Color) -> rusqlite::Result<Option<String>> {
+    let seat_count: u32 = conn.query_row(
+        "SELECT COUNT(*) FROM game_players WHERE game_id=?1",
+        rusqlite::params![game_id],
+        |row| row.get(0),
+    )?;
+
+    if seat_count == 0 {
+        return conn.query_row(
+            "SELECT player_name FROM games WHERE id=?1",
+            rusqlite::params![game_id],
+            |row| row.get(0),
+        );
+    }
+
+    let seat_result = conn.query_row(
+        "SELECT player_name FROM game_players WHERE game_id=?1 AND color=?2",
+        rusqlite::params![game_id, color_to_string(color)],
+        |row| row.get(0),
+    );
+
+    match seat_result {
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        seat => seat,
+    }
+}
+
+pub fn is_game_player(conn: &Connection, game_id: &str, player_name: &str) -> rusqlite::Result<bool> {
+    Ok(get_seat_player(conn, game_id, Color::White)?.as_deref() == Some(player_name)
+        || get_seat_player(conn, game_id, Color::Black)?.as_deref() == Some(player_name))
+}
@@ -0,0 +1,7 @@
+/*
+    The rules and the bots of the game, shared by the server and the tools built on them.
+*/
+pub mod game;
+pub mod opentafl;
+pub mod selfplay;
+pub mod tournament;
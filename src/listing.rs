@@ -0,0 +1,330 @@
+use rusqlite::{Connection, ToSql};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{error::ApiError, game::VARIANT, rating::bot_player_name, stats::{Outcome, game_sides}};
+
+pub const DEFAULT_PAGE_SIZE: u32 = 20;
+pub const MAX_PAGE_SIZE: u32 = 100;
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum GameStatus {
+    Active,
+    Finished,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug, Default, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum GameSort {
+    #[default]
+    CreatedAt,
+    UpdatedAt,
+    MoveCount,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug, Default, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/*
+    Filters, sorting and paging of the games of a player. Every field is optional.
+*/
+#[derive(Deserialize, Serialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GameListQuery {
+    // active or finished
+    pub status: Option<GameStatus>,
+    // w, b, or d for draws
+    pub winner: Option<String>,
+    // The side the player played, w or b
+    pub color: Option<String>,
+    // Only games against the bot of this difficulty
+    pub bot_difficulty: Option<u8>,
+    // Only games created in this range, in milliseconds since the Unix epoch
+    pub created_after_ms: Option<u64>,
+    pub created_before_ms: Option<u64>,
+    // Only copenhagen is played for now
+    pub variant: Option<String>,
+    // Defaults to created_at
+    pub sort: Option<GameSort>,
+    // Defaults to desc
+    pub order: Option<SortOrder>,
+    // Pages are numbered from 1
+    pub page: Option<u32>,
+    // Defaults to 20, at most 100
+    pub per_page: Option<u32>,
+}
+
+/*
+    A game from the point of view of one of its players.
+*/
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug, ToSchema)]
+pub struct GameSummary {
+    pub game_id: String,
+    pub variant: String,
+    // The side of the player, None if they play both sides
+    pub color: Option<String>,
+    // Name of the other side, bots are named like "Krabbi bot 2". None while the seat waits for someone to join
+    pub opponent: Option<String>,
+    // Difficulty of the bot opponent, None against humans
+    pub bot_difficulty: Option<u8>,
+    // x while the game is going, w, b, or d for a draw
+    pub winner: String,
+    pub result_reason: Option<String>,
+    // None while the game is going, and in games against yourself
+    pub outcome: Option<Outcome>,
+    pub move_count: u32,
+    // When the game was created, and when a move, a player joining, or the result last changed it
+    pub created_at_ms: u64,
+    pub updated_at_ms: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug, ToSchema)]
+pub struct GamePage {
+    pub games: Vec<GameSummary>,
+    pub page: u32,
+    pub per_page: u32,
+    // The number of games matching the filters, on every page
+    pub total: u32,
+}
+
+/*
+    Returns one page of the games of a player, with the filters of the query applied.
+    Games are ordered by the sort of the query, then by id, so pages don't overlap.
+*/
+pub fn game_page(conn: &Connection, player_name: &str, query: &GameListQuery) -> Result<GamePage, ApiError> {
+    if query.winner.as_deref().is_some_and(|winner| !["w", "b", "d"].contains(&winner)) {
+        return Err(ApiError::InvalidRequest("winner must be w, b or d".to_string()));
+    }
+
+    if query.color.as_deref().is_some_and(|color| !["w", "b"].contains(&color)) {
+        return Err(ApiError::InvalidColor);
+    }
+
+    let page = query.page.unwrap_or(1);
+    let per_page = query.per_page.unwrap_or(DEFAULT_PAGE_SIZE);
+
+    if page == 0 {
+        return Err(ApiError::InvalidRequest("page must be at least 1".to_string()));
+    }
+
+    if per_page == 0 || per_page > MAX_PAGE_SIZE {
+        return Err(ApiError::InvalidRequest(format!("per_page must be between 1 and {}", MAX_PAGE_SIZE)));
+    }
+
+    // The side of the player is None in games against themselves, and the opponent is the player
+    let sides = game_sides(true);
+    let summaries = format!(
+        "SELECT games.id AS game_id, games.winner, games.bot_difficulty, games.ply, games.created_at, games.updated_at,
+                game_results.reason,
+                CASE
+                    WHEN white.player_name IS :player AND black.player_name IS :player THEN NULL
+                    WHEN white.player_name IS :player THEN 'w'
+                    ELSE 'b'
+                END AS color,
+                CASE WHEN white.player_name IS :player THEN black.player_name ELSE white.player_name END AS opponent,
+                CASE WHEN white.player_name IS :player THEN black.is_bot ELSE white.is_bot END AS opponent_is_bot
+            FROM games
+            JOIN ({sides}) AS white ON white.game_id = games.id AND white.color = 'w'
+            JOIN ({sides}) AS black ON black.game_id = games.id AND black.color = 'b'
+            LEFT JOIN game_results ON game_results.game_id = games.id
+            WHERE white.player_name IS :player OR black.player_name IS :player"
+    );
+
+    let status = match query.status {
+        Some(GameStatus::Active) => "AND winner = 'x'",
+        Some(GameStatus::Finished) => "AND winner != 'x'",
+        None => "",
+    };
+    // Every game is of the one variant there is
+    let variant = match &query.variant {
+        Some(variant) if variant != VARIANT => "AND 0",
+        _ => "",
+    };
+    let mut filters = vec![status, variant];
+    let mut params: Vec<(&str, &dyn ToSql)> = vec![(":player", &player_name)];
+
+    let optional_filters: [(&str, &str, Option<&dyn ToSql>); 5] = [
+        ("AND winner = :winner", ":winner", query.winner.as_ref().map(|winner| winner as &dyn ToSql)),
+        ("AND color = :color", ":color", query.color.as_ref().map(|color| color as &dyn ToSql)),
+        ("AND opponent_is_bot AND bot_difficulty = :bot_difficulty", ":bot_difficulty", query.bot_difficulty.as_ref().map(|difficulty| difficulty as &dyn ToSql)),
+        ("AND created_at >= :created_after", ":created_after", query.created_after_ms.as_ref().map(|after| after as &dyn ToSql)),
+        ("AND created_at < :created_before", ":created_before", query.created_before_ms.as_ref().map(|before| before as &dyn ToSql)),
+    ];
+
+    for (filter, name, value) in optional_filters {
+        if let Some(value) = value {
+            filters.push(filter);
+            params.push((name, value));
+        }
+    }
+
+    let filters = filters.join(" ");
+
+    let total: u32 = conn.query_row(
+        &format!("SELECT COUNT(*) FROM ({summaries}) WHERE 1 {filters}"),
+        params.as_slice(),
+        |row| row.get(0),
+    )?;
+
+    let sort_column = match query.sort.unwrap_or_default() {
+        GameSort::CreatedAt => "created_at",
+        GameSort::UpdatedAt => "updated_at",
+        GameSort::MoveCount => "ply",
+    };
+    let order = match query.order.unwrap_or_default() {
+        SortOrder::Asc => "ASC",
+        SortOrder::Desc => "DESC",
+    };
+    // Can't overflow, unlike u32, with pages far past the last one
+    let offset = i64::from(page - 1) * i64::from(per_page);
+
+    params.push((":limit", &per_page));
+    params.push((":offset", &offset));
+
+    let mut statement = conn.prepare(&format!(
+        "SELECT * FROM ({summaries}) WHERE 1 {filters}
+            ORDER BY {sort_column} {order}, game_id {order}
+            LIMIT :limit OFFSET :offset"
+    ))?;
+
+    let games = statement.query_map(params.as_slice(), |row| {
+        let color: Option<String> = row.get("color")?;
+        let winner: String = row.get("winner")?;
+        let bot_difficulty: u8 = row.get("bot_difficulty")?;
+        let opponent_is_bot: bool = row.get("opponent_is_bot")?;
+
+        let outcome = match &color {
+            Some(_) if winner == "x" => None,
+            Some(_) if winner == "d" => Some(Outcome::Draw),
+            Some(color) if *color == winner => Some(Outcome::Win),
+            Some(_) => Some(Outcome::Loss),
+            None => None,
+        };
+
+        Ok(GameSummary {
+            game_id: row.get("game_id")?,
+            variant: VARIANT.to_string(),
+            color,
+            opponent: if opponent_is_bot { Some(bot_player_name(bot_difficulty)) } else { row.get("opponent")? },
+            bot_difficulty: if opponent_is_bot { Some(bot_difficulty) } else { None },
+            winner,
+            result_reason: row.get("reason")?,
+            outcome,
+            move_count: row.get("ply")?,
+            created_at_ms: row.get("created_at")?,
+            updated_at_ms: row.get("updated_at")?,
+        })
+    })?.collect::<rusqlite::Result<Vec<GameSummary>>>()?;
+
+    Ok(GamePage { games, page, per_page, total })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::migrate, game::Game};
+
+    fn add_game(conn: &Connection, game_id: &str, bot_white: bool, bot_black: bool, seats: &[(&str, &str)], winner: &str, move_times: &[u64]) {
+        let game_state = Game::starting_position(bot_white, bot_black, 2).to_string().unwrap();
+
+        conn.execute(
+            "INSERT INTO games (id, game_state, player_name, winner, bot_difficulty, ply, created_at, updated_at, white_is_bot, black_is_bot)
+                VALUES (?1, ?2, ?3, ?4, 2, ?5, ?6, ?7, ?8, ?9)",
+            rusqlite::params![
+                game_id,
+                game_state,
+                seats[0].1,
+                winner,
+                move_times.len(),
+                move_times.first().unwrap_or(&0),
+                move_times.last().unwrap_or(&0),
+                bot_white,
+                bot_black,
+            ],
+        ).unwrap();
+
+        for (color, player_name) in seats {
+            conn.execute(
+                "INSERT INTO game_players (game_id, color, player_name) VALUES (?1, ?2, ?3)",
+                rusqlite::params![game_id, color, player_name],
+            ).unwrap();
+        }
+
+        for played_at in move_times {
+            crate::moves::record_move(conn, game_id, crate::game::board::Color::Black, (0, 3), (1, 3), &[], *played_at).unwrap();
+        }
+    }
+
+    fn ids(page: &GamePage) -> Vec<&str> {
+        page.games.iter().map(|summary| summary.game_id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_game_page() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+
+        add_game(&conn, "1", true, false, &[("b", "alice")], "w", &[100, 200]);
+        add_game(&conn, "2", false, false, &[("w", "alice"), ("b", "bob")], "d", &[300, 400, 500]);
+        add_game(&conn, "3", false, false, &[("w", "bob"), ("b", "alice")], "x", &[600]);
+        add_game(&conn, "4", false, false, &[("w", "alice")], "x", &[]);
+        add_game(&conn, "5", false, false, &[("w", "bob"), ("b", "carol")], "w", &[700]);
+
+        let all = game_page(&conn, "alice", &GameListQuery::default()).unwrap();
+        assert_eq!(all.total, 4);
+        assert_eq!(ids(&all), vec!["3", "2", "1", "4"]);
+
+        let against_bot = &all.games[2];
+        assert_eq!(against_bot.color.as_deref(), Some("b"));
+        assert_eq!(against_bot.opponent.as_deref(), Some("Krabbi bot 2"));
+        assert_eq!(against_bot.bot_difficulty, Some(2));
+        assert_eq!(against_bot.outcome, Some(Outcome::Loss));
+        assert_eq!(against_bot.move_count, 2);
+        assert_eq!((against_bot.created_at_ms, against_bot.updated_at_ms), (100, 200));
+
+        // The seat of the opponent is still empty
+        assert_eq!(all.games[3].opponent, None);
+        assert_eq!(all.games[3].outcome, None);
+
+        let finished = game_page(&conn, "alice", &GameListQuery { status: Some(GameStatus::Finished), ..Default::default() }).unwrap();
+        assert_eq!(ids(&finished), vec!["2", "1"]);
+        assert_eq!(finished.games[0].outcome, Some(Outcome::Draw));
+
+        let as_white = game_page(&conn, "alice", &GameListQuery { color: Some("w".to_string()), winner: Some("d".to_string()), ..Default::default() }).unwrap();
+        assert_eq!(ids(&as_white), vec!["2"]);
+
+        let against_bots = game_page(&conn, "alice", &GameListQuery { bot_difficulty: Some(2), ..Default::default() }).unwrap();
+        assert_eq!(ids(&against_bots), vec!["1"]);
+
+        let in_range = game_page(&conn, "alice", &GameListQuery { created_after_ms: Some(300), created_before_ms: Some(700), ..Default::default() }).unwrap();
+        assert_eq!(ids(&in_range), vec!["3", "2"]);
+
+        let by_moves = game_page(&conn, "alice", &GameListQuery {
+            sort: Some(GameSort::MoveCount),
+            order: Some(SortOrder::Asc),
+            page: Some(2),
+            per_page: Some(3),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(ids(&by_moves), vec!["2"]);
+        assert_eq!(by_moves.total, 4);
+
+        let other_variant = game_page(&conn, "alice", &GameListQuery { variant: Some("brandubh".to_string()), ..Default::default() }).unwrap();
+        assert_eq!(other_variant.total, 0);
+
+        assert!(game_page(&conn, "alice", &GameListQuery { color: Some("x".to_string()), ..Default::default() }).is_err());
+        assert!(game_page(&conn, "alice", &GameListQuery { page: Some(0), ..Default::default() }).is_err());
+
+        // Pages past the last one are empty, however far
+        let far = game_page(&conn, "alice", &GameListQuery { page: Some(u32::MAX), per_page: Some(MAX_PAGE_SIZE), ..Default::default() }).unwrap();
+        assert!(far.games.is_empty());
+        assert_eq!(far.total, 4);
+        assert!(game_page(&conn, "alice", &GameListQuery { per_page: Some(MAX_PAGE_SIZE + 1), ..Default::default() }).is_err());
+    }
+}
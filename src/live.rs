@@ -0,0 +1,110 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use actix_ws::Session;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::clock::Clock;
+use crate::game::{EndReason, notation::Move};
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum GameEvent {
+    State,
+    Move,
+    BotMove,
+    GameOver,
+    DrawOffered,
+    DrawDeclined,
+}
+
+/*
+    A single update pushed to everyone watching a game.
+    The fen, winner and bot_thinking fields are the same as in the get_board response,
+    so a client can render any update the same way it renders a freshly loaded board.
+    A winner of 'd' means the game ended in a draw.
+*/
+#[derive(Deserialize, Serialize, Clone, Debug, ToSchema)]
+pub struct GameUpdate {
+    pub event: GameEvent,
+    pub fen: String,
+    pub ply: u32,
+    pub winner: String,
+    pub bot_thinking: bool,
+    pub from: Option<(usize, usize)>,
+    pub to: Option<(usize, usize)>,
+    // from and to in tafl notation
+    pub notation: Option<Move>,
+    pub captures: Vec<(usize, usize)>,
+    pub clock: Option<Clock>,
+    pub result_reason: Option<EndReason>,
+    pub draw_offered_by: Option<String>,
+}
+
+/*
+    Keeps track of the open WebSocket sessions of every game, and pushes updates to them.
+    Sessions that fail to receive an update are considered closed, and are dropped.
+*/
+#[derive(Default)]
+pub struct GameHub {
+    // Every session has an id, to find it again after the sends
+    sessions: Mutex<HashMap<String, Vec<(u64, Session)>>>,
+    next_id: AtomicU64,
+}
+
+impl GameHub {
+    pub fn subscribe(&self, game_id: &str, session: Session) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+        self.sessions.lock().unwrap()
+            .entry(game_id.to_string())
+            .or_default()
+            .push((id, session));
+    }
+
+    pub async fn publish(&self, game_id: &str, update: &GameUpdate) {
+        let mut updates = vec![update.clone()];
+
+        if update.winner != "x" && update.event != GameEvent::GameOver {
+            let mut game_over = update.clone();
+            game_over.event = GameEvent::GameOver;
+            updates.push(game_over);
+        }
+
+        // The lock can't be held across the sends, so they go to copies of the sessions.
+        // The sessions stay in the map meanwhile, for other updates and new subscribers.
+        let sessions = match self.sessions.lock().unwrap().get(game_id) {
+            Some(sessions) => sessions.clone(),
+            None => return,
+        };
+
+        let mut closed_sessions = HashSet::new();
+
+        'sessions: for (id, mut session) in sessions {
+            for current_update in updates.iter() {
+                let text = serde_json::to_string(current_update).unwrap();
+
+                if session.text(text).await.is_err() {
+                    closed_sessions.insert(id);
+                    continue 'sessions;
+                }
+            }
+        }
+
+        if closed_sessions.is_empty() {
+            return;
+        }
+
+        let mut all_sessions = self.sessions.lock().unwrap();
+
+        if let Some(sessions) = all_sessions.get_mut(game_id) {
+            sessions.retain(|(id, _)| !closed_sessions.contains(id));
+
+            if sessions.is_empty() {
+                all_sessions.remove(game_id);
+            }
+        }
+    }
+}
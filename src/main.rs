@@ -1,57 +1,379 @@
-use actix_web::{web, get, post, App, HttpResponse, HttpServer, Responder, Result};
+use actix_web::{web, get, post, middleware::from_fn, App, HttpRequest, HttpResponse, HttpServer, Responder, Result};
+use api_v1::mark_deprecated;
+use auth::{Player, bearer_token, find_session_player, require_session};
+use clock::{Clock, TimeControl, load_clock, now_ms, save_clock};
+use db::{DbPool, DEFAULT_DB_PATH, migrate, open_pool};
 use dotenv::dotenv;
-use game::{Game, board::Color};
+use error::ApiError;
+use exhibition::{Exhibition, count_running_exhibitions, load_exhibition, save_exhibition};
+use games::{
+    check_flag, clear_draw_offer, color_to_string, end_game, get_draw_offer, get_rated_name, get_seat_player,
+    is_game_player, load_game, result_to_string, save_result, state_update, string_to_color, update_game_state,
+};
+use krabbi::game::{self, EndReason, Game, LegalMove, board::Color, after_move_eval::get_captured_coords, engine::BotPlayer, notation::{Move, Square}, record::{GameRecord, RecordMove, RecordResult, format_date}};
+use live::{GameEvent, GameHub, GameUpdate};
+use moves::{RecordedMove, load_move, load_moves, record_move};
 use serde::{Deserialize, Serialize};
-use rusqlite::Connection;
-use crate::game::legal_moves::get_legal_moves;
+use std::time::Duration;
+use rusqlite::{Connection, Transaction, TransactionBehavior};
+use sharing::{LiveViewerQuery, ShareLink, ViewerQuery};
 use actix_cors::Cors;
 
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-pub mod game;
+pub mod api_v1;
+pub mod auth;
+pub mod cleanup;
+pub mod clock;
+pub mod db;
+pub mod error;
+pub mod exhibition;
+pub mod games;
+pub mod live;
+pub mod listing;
+pub mod moves;
+pub mod rating;
+pub mod sharing;
+pub mod stats;
+
+#[derive(Deserialize, Serialize, ToSchema)]
+struct CredentialsInfo {
+    player_name: String,
+    password: String,
+}
 
-static DB_NAME: &str = "test.db";
+#[derive(Deserialize, Serialize, ToSchema)]
+struct SessionResponse {
+    player_name: String,
+    token: String,
+}
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, ToSchema)]
 struct NewGameInfo {
-    player_name: String,
     bot_white: bool,
     bot_black: bool,
     bot_difficulty: u8,
+    time_control: Option<TimeControl>,
+    // Only for exhibitions, where bots play both sides: the bot of each side, instead of bot_difficulty
+    white_engine: Option<BotPlayer>,
+    black_engine: Option<BotPlayer>,
+    // Only for exhibitions: how long the bots wait before every move
+    move_delay_ms: Option<u64>,
+}
+
+/*
+    A move, either in tafl notation, or as the coordinates of the board, where x is the row from the top
+    and y the column from the left.
+*/
+#[derive(Deserialize, Serialize, ToSchema)]
+struct MoveInfo {
+    x_from: Option<usize>,
+    y_from: Option<usize>,
+    x_to: Option<usize>,
+    y_to: Option<usize>,
+    notation: Option<Move>,
+    // The ply of the game the move is meant for. Moves for an older ply are rejected,
+    // unless they are a retry of the move that was played at that ply.
+    expected_ply: Option<u32>,
 }
 
 #[derive(Deserialize, Serialize)]
 struct MakeMoveInfo {
-    player_name: String,
     game_id: String,
-    x_from: usize,
-    y_from: usize,
-    x_to: usize,
-    y_to: usize,
+    #[serde(flatten)]
+    move_info: MoveInfo,
 }
 
+/*
+    Without coordinates or a square, legal_moves returns the moves of every piece of the side to move.
+*/
 #[derive(Deserialize, Serialize)]
 struct GetLegalMovesInfo {
-    player_name: String,
     game_id: String,
-    x: usize,
-    y: usize,
+    x: Option<usize>,
+    y: Option<usize>,
+    square: Option<Square>,
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+struct NewOnlineGameInfo {
+    color: String,
+    time_control: Option<TimeControl>,
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+struct GameIdResponse {
+    game_id: String,
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+struct NewOnlineGameResponse {
+    game_id: String,
+    invite_code: String,
 }
 
 #[derive(Deserialize, Serialize)]
-struct GetGamesInfo {
-    player_name: String,
+struct JoinGameInfo {
+    invite_code: String,
 }
 
 #[derive(Deserialize, Serialize)]
 struct GetBoardInfo {
     game_id: String,
+    share_token: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct GameActionInfo {
+    game_id: String,
 }
 
 #[derive(Deserialize, Serialize)]
+struct RespondDrawInfo {
+    game_id: String,
+    accept: bool,
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+struct DrawResponseInfo {
+    accept: bool,
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
 struct BoardInfoResponse {
     fen: String,
+    ply: u32,
     winner: String,
+    bot_thinking: bool,
+    clock: Option<Clock>,
+    result_reason: Option<EndReason>,
+    draw_offered_by: Option<String>,
+}
+
+fn board_info(game: &Game, clock: Option<Clock>, draw_offer: Option<Color>) -> BoardInfoResponse {
+    BoardInfoResponse {
+        fen: game.to_string().unwrap(),
+        ply: game.ply,
+        winner: result_to_string(game),
+        bot_thinking: game.is_bot_turn(),
+        clock,
+        result_reason: game.end_reason,
+        draw_offered_by: draw_offer.map(color_to_string),
+    }
+}
+
+/*
+    Checks that the caller may watch the game: its players, with their session token, and anyone
+    with one of the game's share tokens. Everyone else is told there is no such game.
+    Exhibitions have no players, anyone can watch them.
+    The session token is taken from the Authorization header, query_session_token is only for the WebSocket.
+*/
+fn check_viewer(conn: &Connection, game_id: &str, req: &HttpRequest, share_token: Option<&str>, query_session_token: Option<&str>) -> Result<(), ApiError> {
+    if load_exhibition(conn, game_id)?.is_some() {
+        return Ok(());
+    }
+
+    if let Some(share_token) = share_token {
+        if sharing::is_valid_share_token(conn, game_id, share_token)? {
+            return Ok(());
+        }
+
+        return Err(ApiError::ShareLinkNotFound);
+    }
+
+    let token = match bearer_token(req).or_else(|| query_session_token.map(str::to_string)) {
+        Some(token) => token,
+        None => return Err(ApiError::MissingSessionToken),
+    };
+
+    let player_name = match find_session_player(conn, &token)? {
+        Some(player_name) => player_name,
+        None => return Err(ApiError::InvalidSessionToken),
+    };
+
+    if !is_game_player(conn, game_id, &player_name)? {
+        return Err(ApiError::GameNotFound);
+    }
+
+    Ok(())
+}
+
+/*
+    Returns the side the player plays in the game, or None if they don't play in it.
+    Someone playing both sides of a game is taken to be playing the side to move.
+*/
+fn get_player_color(conn: &Connection, game_id: &str, game: &Game, player_name: &str) -> rusqlite::Result<Option<Color>> {
+    let plays_white = !game.bot_white
+        && get_seat_player(conn, game_id, Color::White)?.as_deref() == Some(player_name);
+    let plays_black = !game.bot_black
+        && get_seat_player(conn, game_id, Color::Black)?.as_deref() == Some(player_name);
+
+    Ok(match (plays_white, plays_black) {
+        (true, true) => Some(game.current_player),
+        (true, false) => Some(Color::White),
+        (false, true) => Some(Color::Black),
+        (false, false) => None,
+    })
+}
+
+// A game loaded for an action, with the transaction it was loaded in, its clock and the side of the player
+type ActionGame<'conn> = (Transaction<'conn>, Game, Option<Clock>, Color);
+
+/*
+    Loads a game for an action of one of its players, and checks that the game is still going.
+    Like play_move, the game is loaded under the write lock of the database, the action has to be stored
+    with the returned transaction. Returns it with the game, its clock, and the side the player plays.
+*/
+async fn load_game_for_action<'conn>(conn: &'conn mut Connection, game_id: &str, player_name: &str, hub: &GameHub) -> Result<ActionGame<'conn>, ApiError> {
+    let transaction = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    let mut game = load_game(&transaction, game_id)?;
+
+    let clock = load_clock(&transaction, game_id)?;
+
+    match check_flag(&transaction, game_id, &mut game, &clock, now_ms()) {
+        Ok(false) => {},
+        Ok(true) => {
+            transaction.commit()?;
+            hub.publish(game_id, &state_update(&game, clock, GameEvent::GameOver)).await;
+            return Err(ApiError::OutOfTime);
+        },
+        Err(err) => return Err(ApiError::Database(err)),
+    }
+
+    if game.is_over() {
+        return Err(ApiError::GameOver);
+    }
+
+    match get_player_color(&transaction, game_id, &game, player_name) {
+        Ok(Some(color)) => Ok((transaction, game, clock, color)),
+        Ok(None) => Err(ApiError::GameNotFound),
+        Err(err) => Err(ApiError::Database(err)),
+    }
+}
+
+/*
+    Plays the bot's move in the game with the given id, and stores the new state.
+    This runs the bot's search, so it must not be called on an actix worker thread.
+*/
+fn play_bot_move(pool: &DbPool, game_id: &str) -> Result<GameUpdate, String> {
+    let mut conn = pool.get().map_err(|err| err.to_string())?;
+
+    // A flag is stored under the write lock, so the game is either ended and rated, or not at all.
+    // The lock is given up before the search, which can take a while.
+    let flag_transaction = conn.transaction_with_behavior(TransactionBehavior::Immediate).map_err(|err| err.to_string())?;
+
+    let mut game = load_game(&flag_transaction, game_id).map_err(|err| format!("{:?}", err))?;
+    let mut clock = load_clock(&flag_transaction, game_id).map_err(|err| err.to_string())?;
+
+    if check_flag(&flag_transaction, game_id, &mut game, &clock, now_ms()).map_err(|err| err.to_string())? {
+        flag_transaction.commit().map_err(|err| err.to_string())?;
+        return Ok(state_update(&game, clock, GameEvent::GameOver));
+    }
+
+    drop(flag_transaction);
+
+    let board_before = game.board;
+    let ply_before = game.ply;
+    let bot_color = game.current_player;
+    let budget = clock.map(|clock| clock.bot_budget(bot_color, now_ms()));
+
+    let mut bot_move = game.make_bot_move(budget).map_err(|err| err.to_string())?;
+    let now = now_ms();
+
+    if let Some(clock) = &mut clock {
+        if clock.end_turn(bot_color, now).is_err() {
+            // The search overran the clock, the move doesn't count
+            game.board = board_before;
+            game.current_player = bot_color;
+            game.ply = ply_before;
+            game.lose_on_time(bot_color);
+            bot_move = None;
+        }
+    }
+
+    if game.bot_white && game.bot_black && game.ply >= exhibition::MAX_PLIES && !game.is_over() {
+        game.draw_by_move_limit();
+    }
+
+    let new_fen = game.to_string()?;
+    let winner = result_to_string(&game);
+    let captures = get_captured_coords(&board_before, &game.board, bot_color);
+
+    let transaction = conn.transaction().map_err(|err| err.to_string())?;
+
+    // Fails if the game was resigned while the bot was thinking
+    update_game_state(&transaction, game_id, &game, ply_before).map_err(|err| err.to_string())?;
+
+    if let Some(bot_move) = bot_move {
+        record_move(&transaction, game_id, bot_color, bot_move[0], bot_move[1], &captures, now).map_err(|err| err.to_string())?;
+    }
+
+    if let Some(clock) = &clock {
+        save_clock(&transaction, game_id, clock).map_err(|err| err.to_string())?;
+    }
+
+    if game.is_over() {
+        save_result(&transaction, game_id, &game).map_err(|err| err.to_string())?;
+    }
+
+    transaction.commit().map_err(|err| err.to_string())?;
+
+    Ok(GameUpdate {
+        event: GameEvent::BotMove,
+        fen: new_fen,
+        ply: game.ply,
+        winner,
+        bot_thinking: game.is_bot_turn(),
+        from: bot_move.map(|m| m[0]),
+        to: bot_move.map(|m| m[1]),
+        notation: bot_move.and_then(|m| Move::from_coords(m[0], m[1])),
+        captures,
+        clock,
+        result_reason: game.end_reason,
+        draw_offered_by: None,
+    })
+}
+
+/*
+    Schedules the bot's reply on the blocking thread pool, so the request that made
+    the human move can return right away. Clients see the reply through get_board,
+    or get it pushed through the game's WebSocket.
+    In exhibitions the bots keep replying to each other, with the game's delay before every move, until the game is over.
+*/
+fn spawn_bot_move(game_id: String, pool: DbPool, hub: web::Data<GameHub>) {
+    actix_web::rt::spawn(async move {
+        loop {
+            let exhibition_pool = pool.clone();
+            let exhibition_game_id = game_id.clone();
+            let move_delay_ms = match web::block(move || {
+                load_exhibition(&*exhibition_pool.get()?, &exhibition_game_id).map_err(ApiError::Database)
+            }).await {
+                Ok(Ok(exhibition)) => exhibition.map(|exhibition| exhibition.move_delay_ms),
+                Ok(Err(err)) => return println!("Bot move failed: {}", err),
+                Err(err) => return println!("Bot move could not be scheduled: {:?}", err),
+            };
+
+            if let Some(move_delay_ms) = move_delay_ms {
+                actix_web::rt::time::sleep(Duration::from_millis(move_delay_ms)).await;
+            }
+
+            let bot_pool = pool.clone();
+            let bot_game_id = game_id.clone();
+            match web::block(move || play_bot_move(&bot_pool, &bot_game_id)).await {
+                Ok(Ok(update)) => {
+                    hub.publish(&game_id, &update).await;
+
+                    if move_delay_ms.is_none() || !update.bot_thinking {
+                        return;
+                    }
+                },
+                Ok(Err(err)) => return println!("Bot move failed: {}", err),
+                Err(err) => return println!("Bot move could not be scheduled: {:?}", err),
+            }
+        }
+    });
 }
 
 #[get("/api")]
@@ -59,302 +381,1042 @@ async fn hello() -> impl Responder {
     HttpResponse::Ok().body("Hello world!\n")
 }
 
-#[post("/api/new_game")]
-async fn new_game(new_game_info: web::Json<NewGameInfo>) -> Result<String> {
-    let conn = Connection::open(DB_NAME).expect(&format!("Failed database connection to {}",DB_NAME).to_owned());
+/*
+    Creates an account, and logs the new player in.
+*/
+fn create_account(pool: &DbPool, credentials: &CredentialsInfo) -> Result<SessionResponse, ApiError> {
+    let conn = pool.get()?;
+
+    if credentials.player_name.trim().is_empty() || credentials.password.is_empty() {
+        return Err(ApiError::EmptyCredentials);
+    }
 
-    if new_game_info.bot_white && new_game_info.bot_black {
-        return Err(actix_web::error::ErrorInternalServerError("Cannot create a game without a human player!"));
+    // The bots are rated under these names
+    if rating::is_bot_name(&credentials.player_name) {
+        return Err(ApiError::NameTaken);
     }
 
+    let password_hash = match auth::hash_password(&credentials.password) {
+        Ok(password_hash) => password_hash,
+        Err(err) => return Err(ApiError::Internal(err)),
+    };
+
+    let insert_result = conn.execute(
+        "INSERT INTO players (name, password_hash) VALUES (?1, ?2)",
+        rusqlite::params![credentials.player_name, password_hash],
+    );
+
+    match insert_result {
+        Ok(_) => {},
+        Err(rusqlite::Error::SqliteFailure(err, _)) if err.code == rusqlite::ErrorCode::ConstraintViolation => {
+            return Err(ApiError::NameTaken);
+        },
+        Err(err) => return Err(ApiError::Database(err)),
+    }
+
+    match auth::create_session(&conn, &credentials.player_name) {
+        Ok(token) => Ok(SessionResponse {
+            player_name: credentials.player_name.clone(),
+            token,
+        }),
+        Err(err) => Err(ApiError::Database(err)),
+    }
+}
+
+#[post("/api/register", wrap = "from_fn(mark_deprecated)")]
+async fn register(credentials: web::Json<CredentialsInfo>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(create_account(&pool, &credentials)?))
+}
+
+fn start_session(pool: &DbPool, credentials: &CredentialsInfo) -> Result<SessionResponse, ApiError> {
+    let conn = pool.get()?;
+
+    let hash_result = conn.query_row(
+        "SELECT password_hash FROM players WHERE name=?1",
+        rusqlite::params![credentials.player_name],
+        |row| row.get::<usize, String>(0),
+    );
+
+    let password_hash = match hash_result {
+        Ok(password_hash) => password_hash,
+        Err(rusqlite::Error::QueryReturnedNoRows) => return Err(ApiError::WrongCredentials),
+        Err(err) => return Err(ApiError::Database(err)),
+    };
+
+    if !auth::verify_password(&credentials.password, &password_hash) {
+        return Err(ApiError::WrongCredentials);
+    }
+
+    match auth::create_session(&conn, &credentials.player_name) {
+        Ok(token) => Ok(SessionResponse {
+            player_name: credentials.player_name.clone(),
+            token,
+        }),
+        Err(err) => Err(ApiError::Database(err)),
+    }
+}
+
+#[post("/api/login", wrap = "from_fn(mark_deprecated)")]
+async fn login(credentials: web::Json<CredentialsInfo>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(start_session(&pool, &credentials)?))
+}
+
+fn end_session(pool: &DbPool, req: &HttpRequest) -> Result<(), ApiError> {
+    let conn = pool.get()?;
+
+    // require_session already made sure the header holds a valid token
+    let token = auth::bearer_token(req).unwrap_or_default();
+
+    match auth::delete_session(&conn, &token) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(ApiError::Database(err)),
+    }
+}
+
+#[post("/api/logout", wrap = "from_fn(require_session)", wrap = "from_fn(mark_deprecated)")]
+async fn logout(req: HttpRequest, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    end_session(&pool, &req)?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/*
+    Returns the exhibition the new game is, if bots play both of its sides.
+    Engines and a move delay can only be chosen for exhibitions.
+*/
+fn new_exhibition(new_game_info: &NewGameInfo) -> Result<Option<Exhibition>, ApiError> {
+    if !(new_game_info.bot_white && new_game_info.bot_black) {
+        if new_game_info.white_engine.is_some() || new_game_info.black_engine.is_some() || new_game_info.move_delay_ms.is_some() {
+            return Err(ApiError::InvalidRequest("engines and a move delay are only for games between two bots".to_string()));
+        }
+
+        return Ok(None);
+    }
+
+    let level = BotPlayer::from_level(new_game_info.bot_difficulty);
+    let (Some(white), Some(black)) = (new_game_info.white_engine.or(level), new_game_info.black_engine.or(level)) else {
+        return Err(ApiError::InvalidBotDifficulty);
+    };
+
+    if !white.is_valid() || !black.is_valid() {
+        return Err(ApiError::InvalidBotDifficulty);
+    }
+
+    let move_delay_ms = new_game_info.move_delay_ms.unwrap_or(exhibition::DEFAULT_MOVE_DELAY_MS);
+    if move_delay_ms > exhibition::MAX_MOVE_DELAY_MS {
+        return Err(ApiError::InvalidRequest(format!("move_delay_ms must be at most {}", exhibition::MAX_MOVE_DELAY_MS)));
+    }
+
+    Ok(Some(Exhibition { white, black, move_delay_ms }))
+}
+
+fn create_game(pool: &DbPool, new_game_info: &NewGameInfo, player: &Player, hub: &web::Data<GameHub>) -> Result<String, ApiError> {
+    if !(1..=2).contains(&new_game_info.bot_difficulty) {
+        return Err(ApiError::InvalidBotDifficulty);
+    }
+
+    if let Some(time_control) = &new_game_info.time_control {
+        time_control.validate()?;
+    }
+
+    let mut conn = pool.get()?;
+
+    let exhibition = new_exhibition(new_game_info)?;
+
     let bot_difficulty = &new_game_info.bot_difficulty;
-    let new_game: Game = Game::new(
+    let mut game: Game = Game::starting_position(
                         new_game_info.bot_white, 
                         new_game_info.bot_black, 
                         new_game_info.bot_difficulty
                     );
-    let player_name = &new_game_info.player_name;
-    let new_game_id = Uuid::new_v4().to_string(); 
-    let winner: String = match new_game.get_winner() {
-        Color::White => "w".to_string(),
-        Color::Black => "b".to_string(),
-        Color::None => "x".to_string(),
+    // Stored as the first move of the game, the same as the other moves of the bot.
+    // The bots of exhibitions play every move themselves.
+    let opening_move = if new_game_info.bot_black && exhibition.is_none() {
+        game.make_opening_move()
+    } else {
+        None
     };
-    if bot_difficulty > &2 || bot_difficulty< &1 {
-        return Err(actix_web::error::ErrorInternalServerError("Bot difficulty must be 1, 2 or 3!"));
-    }
-
-    let result = conn.execute(
-        "INSERT INTO games (id, game_state, player_name, winner, bot_difficulty) 
-            VALUES (?1, ?2, ?3, ?4, ?5)",
-        [
-            &new_game_id, 
-            &new_game.to_string().unwrap(), 
-            &player_name.to_string(), 
-            &winner, 
-            &bot_difficulty.to_string()
+    // Nobody plays in an exhibition, not even the player who started it
+    let player_name = if exhibition.is_none() { Some(&player.name) } else { None };
+    let new_game_id = Uuid::new_v4().to_string(); 
+    let winner: String = color_to_string(game.get_winner());
+    let game_state = game.to_string().map_err(ApiError::Internal)?;
+    let now = now_ms();
+
+    // Under the write lock, so exhibitions started at the same time can't both take the last place
+    let transaction = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    if exhibition.is_some() {
+        let (running, started_by_player) = count_running_exhibitions(&transaction, &player.name)?;
+
+        if running >= exhibition::MAX_RUNNING_EXHIBITIONS || started_by_player >= exhibition::MAX_RUNNING_EXHIBITIONS_PER_PLAYER {
+            return Err(ApiError::TooManyExhibitions);
+        }
+    }
+
+    transaction.execute(
+        "INSERT INTO games (id, game_state, player_name, winner, bot_difficulty, ply, created_at, updated_at, white_is_bot, black_is_bot) 
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?7, ?8, ?9)",
+        rusqlite::params![
+            new_game_id, 
+            game_state, 
+            player_name, 
+            winner, 
+            bot_difficulty,
+            game.ply,
+            now,
+            new_game_info.bot_white,
+            new_game_info.bot_black,
         ],
-    );
-   
-    if result.is_ok() {
-        Ok(new_game_id)
-    } else {
-        let err = result.unwrap_err();
-        println!("{:?}", err);
-        Err(actix_web::error::ErrorInternalServerError("Could not create new game!"))
+    )?;
+
+    for (color, is_bot) in [(Color::White, new_game_info.bot_white), (Color::Black, new_game_info.bot_black)] {
+        if is_bot {
+            continue;
+        }
+
+        transaction.execute(
+            "INSERT INTO game_players (game_id, color, player_name) VALUES (?1, ?2, ?3)",
+            rusqlite::params![new_game_id, color_to_string(color), player_name],
+        )?;
     }
-}
 
-#[post("/api/make_move")]
-async fn make_move(make_move_info: web::Json<MakeMoveInfo>) -> Result<HttpResponse, actix_web::error::Error> {
-    let conn = Connection::open(DB_NAME).expect(&format!("Failed database connection to {}",DB_NAME).to_owned());
+    if let Some(opening_move) = opening_move {
+        record_move(&transaction, &new_game_id, Color::Black, opening_move[0], opening_move[1], &[], now)?;
+    }
 
-    if make_move_info.x_from > 10 
-        || make_move_info.y_from > 10
-        || make_move_info.x_to > 10
-        || make_move_info.y_to > 10 {
-        return Err(actix_web::error::ErrorInternalServerError("Incorrect index!"));
+    if let Some(time_control) = new_game_info.time_control {
+        save_clock(&transaction, &new_game_id, &Clock::new(time_control, now))?;
     }
 
-    let statement_result = conn.prepare("SELECT * FROM games WHERE id=?1 AND player_name=?2");
+    if let Some(exhibition) = &exhibition {
+        save_exhibition(&transaction, &new_game_id, exhibition, &player.name)?;
+    }
 
-    if statement_result.is_err() {
-        return Err(actix_web::error::ErrorInternalServerError("SQL error"));
+    transaction.commit()?;
+
+    if exhibition.is_some() {
+        spawn_bot_move(new_game_id.clone(), pool.clone(), hub.clone());
     }
 
-    let mut statement = statement_result.unwrap();
+    Ok(new_game_id)
+}
 
-    let rows_result = statement.query(rusqlite::params![make_move_info.game_id, make_move_info.player_name]);
+#[post("/api/new_game", wrap = "from_fn(require_session)", wrap = "from_fn(mark_deprecated)")]
+async fn new_game(new_game_info: web::Json<NewGameInfo>, player: web::ReqData<Player>, hub: web::Data<GameHub>, pool: web::Data<DbPool>) -> Result<String, ApiError> {
+    create_game(&pool, &new_game_info, &player, &hub)
+}
+
+/*
+    Returns the squares of the move, which has to be given either in notation or with all four coordinates.
+*/
+fn move_coords(move_info: &MoveInfo) -> Result<[(usize, usize); 2], ApiError> {
+    let coords = [move_info.x_from, move_info.y_from, move_info.x_to, move_info.y_to];
+
+    match (move_info.notation, coords) {
+        (Some(notation), [None, None, None, None]) => Ok([notation.from.coords(), notation.to.coords()]),
+        (Some(_), _) => Err(ApiError::InvalidRequest("a move has either notation or coordinates, not both".to_string())),
+        (None, [Some(x_from), Some(y_from), Some(x_to), Some(y_to)]) => {
+            if x_from > 10 || y_from > 10 || x_to > 10 || y_to > 10 {
+                return Err(ApiError::InvalidIndex);
+            }
 
-    if rows_result.is_err() {
-        return Err(actix_web::error::ErrorInternalServerError("Database query error"));
+            Ok([(x_from, y_from), (x_to, y_to)])
+        },
+        (None, _) => Err(ApiError::MissingCoordinate),
     }
+}
+
+async fn play_move(pool: &DbPool, game_id: &str, move_info: &MoveInfo, player: &Player, hub: &web::Data<GameHub>) -> Result<BoardInfoResponse, ApiError> {
+    let mut conn = pool.get()?;
+
+    let [from, to] = move_coords(move_info)?;
+
+    // Reading the game and storing the move happen under the write lock of the database,
+    // so two requests at the same time can't both move from the same position
+    let transaction = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    let mut game = load_game(&transaction, game_id)?;
 
-    let mut rows = rows_result.unwrap();
+    let mut clock = load_clock(&transaction, game_id)?;
 
-    let chfen: String;
-    let difficulty: u8;
+    // The flag is checked at the time the move is made, so the clock can't run out in between
+    let now = now_ms();
 
-    if let Some(row) = rows.next().transpose() {
-        if row.is_err() {
-            return Err(actix_web::error::ErrorInternalServerError("No game found"));
+    match check_flag(&transaction, game_id, &mut game, &clock, now) {
+        Ok(false) => {},
+        Ok(true) => {
+            transaction.commit()?;
+            hub.publish(game_id, &state_update(&game, clock, GameEvent::GameOver)).await;
+            return Err(ApiError::OutOfTime);
+        },
+        Err(err) => return Err(ApiError::Database(err)),
+    }
+
+    if let Some(expected_ply) = move_info.expected_ply {
+        if expected_ply != game.ply {
+            let played_move = load_move(&transaction, game_id, expected_ply + 1)?;
+
+            // A retry of a move that was already played gets the same answer as the first try,
+            // as long as it is the same move, and the player plays the side that made it
+            return match played_move {
+                Some(RecordedMove { from: played_from, to: played_to, color: Some(played_by), .. })
+                    if played_from == from && played_to == to
+                    && get_seat_player(&transaction, game_id, string_to_color(&played_by))?.as_deref() == Some(player.name.as_str()) => {
+                    let draw_offer = get_draw_offer(&transaction, game_id)?;
+                    Ok(board_info(&game, clock, draw_offer))
+                },
+                _ => Err(ApiError::StaleMove),
+            };
         }
-        let row_data = row.unwrap();
-        chfen = row_data.get("game_state").unwrap();
-        difficulty = row_data.get("bot_difficulty").expect("No bot difficulty in db row");
-    } else {
-        return Err(actix_web::error::ErrorInternalServerError("No game found"));
     }
 
-    let game_result = Game::from_string(chfen, difficulty);
+    if game.is_over() {
+        return Err(ApiError::GameOver);
+    }
+
+    if game.is_bot_turn() {
+        return Err(ApiError::BotThinking);
+    }
 
-    if game_result.is_err() {
-        return Err(actix_web::error::ErrorInternalServerError("Error parsing FEN"));
+    match get_seat_player(&transaction, game_id, game.current_player) {
+        Ok(Some(seat_player)) if seat_player == player.name => {},
+        Ok(Some(_)) => return Err(ApiError::NotYourTurn),
+        Ok(None) => return Err(ApiError::WaitingForOpponent),
+        Err(err) => return Err(ApiError::Database(err)),
     }
 
-    let mut game = game_result.unwrap();
+    let board_before = game.board;
+    let ply_before = game.ply;
+    let mover = game.current_player;
 
-    println!("trying to make move: {},{} -> {},{}", 
-             make_move_info.x_from, 
-             make_move_info.y_from,
-             make_move_info.x_to,
-             make_move_info.y_to);
-    match game.make_move(make_move_info.x_from, make_move_info.y_from, make_move_info.x_to, make_move_info.y_to) {
+    match game.make_move(from.0, from.1, to.0, to.1) {
         Ok(_) => {
-            let new_fen = game.to_string().unwrap();
+            let new_fen = game.to_string().map_err(ApiError::Internal)?;
+            let winner_char = result_to_string(&game);
+            let captures = get_captured_coords(&board_before, &game.board, mover);
 
-            let winner_char = match game.get_winner() {
-                Color::White => 'w'.to_string(),
-                Color::Black => 'b'.to_string(),
-                Color::None => 'x'.to_string(),
-            };
-            let update_result = conn.prepare("UPDATE games SET game_state=?1, winner=?2 WHERE id=?3 AND player_name=?4");
+            update_game_state(&transaction, game_id, &game, ply_before)?;
+
+            record_move(&transaction, game_id, mover, from, to, &captures, now)?;
 
-            if update_result.is_err() {
-                return Err(actix_web::error::ErrorInternalServerError("SQL error"));
+            // Moving declines any draw the opponent offered
+            if game.is_over() {
+                save_result(&transaction, game_id, &game)?;
+            } else {
+                clear_draw_offer(&transaction, game_id)?;
             }
 
-            let mut update = update_result.unwrap();
+            if let Some(clock) = &mut clock {
+                // The flag was checked at the same time, so running out here is a bug
+                clock.end_turn(mover, now).map_err(|err| ApiError::Internal(err.to_string()))?;
 
-            let update_query_result = update.execute(rusqlite::params![new_fen, winner_char, make_move_info.game_id, make_move_info.player_name]);
+                save_clock(&transaction, game_id, clock)?;
+            }
 
-            if update_query_result.is_err() {
-                return Err(actix_web::error::ErrorInternalServerError("Unable to update database!".to_string()));
+            transaction.commit()?;
+
+            let bot_thinking = game.is_bot_turn();
+
+            hub.publish(game_id, &GameUpdate {
+                event: GameEvent::Move,
+                fen: new_fen.clone(),
+                ply: game.ply,
+                winner: winner_char.clone(),
+                bot_thinking,
+                from: Some(from),
+                to: Some(to),
+                notation: Move::from_coords(from, to),
+                captures,
+                clock,
+                result_reason: game.end_reason,
+                draw_offered_by: None,
+            }).await;
+
+            if bot_thinking {
+                spawn_bot_move(game_id.to_string(), pool.clone(), hub.clone());
             }
 
-            Ok(HttpResponse::Ok().json(BoardInfoResponse {
-                fen: new_fen,
-                winner: winner_char,
-            }))
+            Ok(board_info(&game, clock, None))
         },
         Err(err) => {
-            // if err == "Game is over!" {
-            //     return Ok(HttpResponse::Ok().json(BoardInfoResponse {
-            //         fen: new_fen,
-            //         winner: winner_char,
-            //     }));
-            // }
-
-            Err(actix_web::error::ErrorInternalServerError(format!("Invalid move: {}", err)))
+            Err(ApiError::IllegalMove(err.to_string()))
         },
     }
 }
 
-#[post("/api/legal_moves")]
-async fn legal_moves(legal_moves_info: web::Json<GetLegalMovesInfo>) -> Result<String> {
-    let conn = Connection::open(DB_NAME).expect(&format!("Failed database connection to {}",DB_NAME).to_owned());
+#[post("/api/make_move", wrap = "from_fn(require_session)", wrap = "from_fn(mark_deprecated)")]
+async fn make_move(make_move_info: web::Json<MakeMoveInfo>, player: web::ReqData<Player>, hub: web::Data<GameHub>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(play_move(&pool, &make_move_info.game_id, &make_move_info.move_info, &player, &hub).await?))
+}
 
-    if legal_moves_info.x > 10 
-        || legal_moves_info.y > 10 {
-        return Err(actix_web::error::ErrorInternalServerError("Incorrect index!"));
+/*
+    Returns the piece whose moves are asked for, given either as a square or with both coordinates,
+    or None for the moves of every piece.
+*/
+fn piece_coords(x: Option<usize>, y: Option<usize>, square: Option<Square>) -> Result<Option<(usize, usize)>, ApiError> {
+    match (x, y, square) {
+        (None, None, square) => Ok(square.map(|square| square.coords())),
+        (_, _, Some(_)) => Err(ApiError::InvalidRequest("a piece is given either as a square or with coordinates, not both".to_string())),
+        (Some(x), Some(y), None) if x > 10 || y > 10 => Err(ApiError::InvalidIndex),
+        (Some(x), Some(y), None) => Ok(Some((x, y))),
+        _ => Err(ApiError::MissingCoordinate),
     }
+}
 
-    let statement_result = conn.prepare("SELECT * FROM games WHERE id=?1 AND player_name=?2");
+fn list_legal_moves(pool: &DbPool, game_id: &str, piece: Option<(usize, usize)>, player: &Player) -> Result<Vec<LegalMove>, ApiError> {
+    let conn = pool.get()?;
 
-    if statement_result.is_err() {
-        return Err(actix_web::error::ErrorInternalServerError("SQL error"));
+    match is_game_player(&conn, game_id, &player.name) {
+        Ok(true) => {},
+        Ok(false) | Err(rusqlite::Error::QueryReturnedNoRows) => return Err(ApiError::GameNotFound),
+        Err(err) => return Err(ApiError::Database(err)),
     }
 
-    let mut statement = statement_result.unwrap();
+    let game = load_game(&conn, game_id)?;
+
+    let moves: Vec<LegalMove> = match piece {
+        Some((x, y)) => {
+            if game.is_over() {
+                Vec::new()
+            } else {
+                match game.get_piece_moves(x, y) {
+                    Ok(moves) => moves,
+                    Err(_) => return Err(ApiError::NotYourPiece),
+                }
+            }
+        },
+        None => game.get_all_moves(),
+    };
 
-    let rows_result = statement.query(rusqlite::params![legal_moves_info.game_id, legal_moves_info.player_name]);
+    Ok(moves)
+}
+
+#[post("/api/legal_moves", wrap = "from_fn(require_session)", wrap = "from_fn(mark_deprecated)")]
+async fn legal_moves(legal_moves_info: web::Json<GetLegalMovesInfo>, player: web::ReqData<Player>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let piece = piece_coords(legal_moves_info.x, legal_moves_info.y, legal_moves_info.square)?;
+
+    Ok(HttpResponse::Ok().json(list_legal_moves(&pool, &legal_moves_info.game_id, piece, &player)?))
+}
+
+fn list_games(pool: &DbPool, player: &Player) -> Result<Vec<String>, ApiError> {
+    let conn = pool.get()?;
+
+    let mut statement = conn.prepare(
+        "SELECT id FROM games WHERE player_name=?1
+            UNION SELECT game_id FROM game_players WHERE player_name=?1"
+    )?;
 
-    if rows_result.is_err() {
-        return Err(actix_web::error::ErrorInternalServerError("Database query error"));
+    let rows = statement.query_map(rusqlite::params![player.name], |row| {
+        row.get::<usize, String>(0)
+    })?.collect::<rusqlite::Result<Vec<String>>>()?;
+
+    Ok(rows)
+}
+
+#[post("/api/get_games", wrap = "from_fn(require_session)", wrap = "from_fn(mark_deprecated)")]
+async fn get_games(player: web::ReqData<Player>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(list_games(&pool, &player)?))
+}
+
+/*
+    Creates a game between two humans. The creator takes the given side, and the other
+    side waits for whoever joins with the returned invite code.
+*/
+fn create_online_game(pool: &DbPool, new_online_game_info: &NewOnlineGameInfo, player: &Player) -> Result<NewOnlineGameResponse, ApiError> {
+    let mut conn = pool.get()?;
+
+    let (creator_color, opponent_color) = match new_online_game_info.color.as_str() {
+        "w" => (Color::White, Color::Black),
+        "b" => (Color::Black, Color::White),
+        _ => return Err(ApiError::InvalidColor),
+    };
+
+    if let Some(time_control) = &new_online_game_info.time_control {
+        time_control.validate()?;
     }
 
-    let mut rows = rows_result.unwrap();
+    let game = Game::new(false, false, 1);
+    let game_state = game.to_string().map_err(ApiError::Internal)?;
+    let new_game_id = Uuid::new_v4().to_string();
+    let invite_code = Uuid::new_v4().to_string();
+    let now = now_ms();
+
+    let transaction = conn.transaction()?;
+
+    transaction.execute(
+        "INSERT INTO games (id, game_state, player_name, winner, bot_difficulty, created_at, updated_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)",
+        rusqlite::params![
+            new_game_id,
+            game_state,
+            player.name,
+            color_to_string(game.get_winner()),
+            1,
+            now,
+        ],
+    )?;
+
+    transaction.execute(
+        "INSERT INTO game_players (game_id, color, player_name) VALUES (?1, ?2, ?3)",
+        rusqlite::params![new_game_id, color_to_string(creator_color), player.name],
+    )?;
+
+    transaction.execute(
+        "INSERT INTO game_players (game_id, color, invite_code) VALUES (?1, ?2, ?3)",
+        rusqlite::params![new_game_id, color_to_string(opponent_color), invite_code],
+    )?;
+
+    if let Some(time_control) = new_online_game_info.time_control {
+        save_clock(&transaction, &new_game_id, &Clock::new(time_control, now))?;
+    }
+
+    transaction.commit()?;
+
+    Ok(NewOnlineGameResponse {
+        game_id: new_game_id,
+        invite_code,
+    })
+}
+
+#[post("/api/new_online_game", wrap = "from_fn(require_session)", wrap = "from_fn(mark_deprecated)")]
+async fn new_online_game(new_online_game_info: web::Json<NewOnlineGameInfo>, player: web::ReqData<Player>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(create_online_game(&pool, &new_online_game_info, &player)?))
+}
+
+/*
+    Takes the open seat of the game the invite code belongs to. Returns the game's id.
+    The seat is claimed and the clock started under the write lock of the database,
+    so two players joining at the same time can't both get the seat.
+*/
+async fn join_with_invite(pool: &DbPool, invite_code: &str, player: &Player, hub: &GameHub) -> Result<String, ApiError> {
+    let mut conn = pool.get()?;
+
+    let transaction = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
 
-    let chfen: String;
-    let difficulty: u8;
+    let seat_result = transaction.query_row(
+        "SELECT game_id, player_name FROM game_players WHERE invite_code=?1",
+        rusqlite::params![invite_code],
+        |row| Ok((row.get::<usize, String>(0)?, row.get::<usize, Option<String>>(1)?)),
+    );
 
-    if let Some(row) = rows.next().transpose() {
-        if row.is_err() {
-            return Err(actix_web::error::ErrorInternalServerError("No game found"));
+    let game_id = match seat_result {
+        Ok((game_id, None)) => game_id,
+        Ok((_, Some(_))) => return Err(ApiError::GameFull),
+        Err(rusqlite::Error::QueryReturnedNoRows) => return Err(ApiError::InviteNotFound),
+        Err(err) => return Err(ApiError::Database(err)),
+    };
+
+    // The creator can't take the other seat with their own invite
+    if is_game_player(&transaction, &game_id, &player.name)? {
+        return Err(ApiError::AlreadyJoined);
+    }
+
+    let update_result = transaction.execute(
+        "UPDATE game_players SET player_name=?1 WHERE invite_code=?2 AND player_name IS NULL",
+        rusqlite::params![player.name, invite_code],
+    );
+
+    match update_result {
+        Ok(1) => {},
+        Ok(_) => return Err(ApiError::GameFull),
+        Err(err) => return Err(ApiError::Database(err)),
+    }
+
+    let now = now_ms();
+
+    transaction.execute("UPDATE games SET updated_at=?1 WHERE id=?2", rusqlite::params![now, game_id])?;
+
+    let game = load_game(&transaction, &game_id)?;
+
+    // The clock only starts once both players are there
+    let mut clock = load_clock(&transaction, &game_id)?;
+
+    if let Some(clock) = &mut clock {
+        clock.turn_started_at_ms = now;
+
+        save_clock(&transaction, &game_id, clock)?;
+    }
+
+    transaction.commit()?;
+
+    hub.publish(&game_id, &state_update(&game, clock, GameEvent::State)).await;
+
+    Ok(game_id)
+}
+
+#[post("/api/join_game", wrap = "from_fn(require_session)", wrap = "from_fn(mark_deprecated)")]
+async fn join_game(join_game_info: web::Json<JoinGameInfo>, player: web::ReqData<Player>, hub: web::Data<GameHub>, pool: web::Data<DbPool>) -> Result<String, ApiError> {
+    join_with_invite(&pool, &join_game_info.invite_code, &player, &hub).await
+}
+
+async fn resign_game(pool: &DbPool, game_id: &str, player: &Player, hub: &GameHub) -> Result<BoardInfoResponse, ApiError> {
+    let mut conn = pool.get()?;
+
+    let (transaction, mut game, clock, color) = load_game_for_action(&mut conn, game_id, &player.name, hub).await?;
+
+    game.resign(color);
+
+    if !end_game(&transaction, game_id, &game)? {
+        return Err(ApiError::StaleMove);
+    }
+
+    transaction.commit()?;
+
+    hub.publish(game_id, &state_update(&game, clock, GameEvent::GameOver)).await;
+
+    Ok(board_info(&game, clock, None))
+}
+
+#[post("/api/resign", wrap = "from_fn(require_session)", wrap = "from_fn(mark_deprecated)")]
+async fn resign(resign_info: web::Json<GameActionInfo>, player: web::ReqData<Player>, hub: web::Data<GameHub>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(resign_game(&pool, &resign_info.game_id, &player, &hub).await?))
+}
+
+/*
+    Offers a draw to the opponent. Bots answer right away, based on their evaluation of the
+    position, human opponents answer with respond_draw, or by making a move.
+    Someone playing both sides of a game gets their draw right away.
+*/
+async fn propose_draw(pool: &DbPool, game_id: &str, player: &Player, hub: &GameHub) -> Result<BoardInfoResponse, ApiError> {
+    let mut conn = pool.get()?;
+
+    let (transaction, mut game, clock, color) = load_game_for_action(&mut conn, game_id, &player.name, hub).await?;
+    let opponent = color.opponent();
+
+    let existing_offer = get_draw_offer(&transaction, game_id)?;
+
+    let opponent_is_bot = (opponent == Color::White && game.bot_white) || (opponent == Color::Black && game.bot_black);
+
+    let accepted = if existing_offer == Some(color) {
+        return Err(ApiError::DrawAlreadyOffered);
+    } else if existing_offer == Some(opponent) {
+        // Both sides want a draw
+        true
+    } else if opponent_is_bot {
+        game.bot_accepts_draw(opponent)
+    } else {
+        match get_seat_player(&transaction, game_id, opponent) {
+            Ok(opponent_player) => opponent_player.as_deref() == Some(player.name.as_str()),
+            Err(err) => return Err(ApiError::Database(err)),
+        }
+    };
+
+    if accepted {
+        game.agree_draw();
+
+        if !end_game(&transaction, game_id, &game)? {
+            return Err(ApiError::StaleMove);
         }
-        let row_data = row.unwrap();
-        chfen = row_data.get("game_state").unwrap();
-        difficulty = row_data.get("bot_difficulty").expect("No bot difficulty in db row");
+
+        transaction.commit()?;
+
+        hub.publish(game_id, &state_update(&game, clock, GameEvent::GameOver)).await;
+
+        return Ok(board_info(&game, clock, None));
+    }
+
+    if opponent_is_bot {
+        hub.publish(game_id, &state_update(&game, clock, GameEvent::DrawDeclined)).await;
+
+        return Ok(board_info(&game, clock, None));
+    }
+
+    transaction.execute(
+        "INSERT OR REPLACE INTO draw_offers (game_id, color) VALUES (?1, ?2)",
+        rusqlite::params![game_id, color_to_string(color)],
+    )?;
+
+    transaction.commit()?;
+
+    let mut update = state_update(&game, clock, GameEvent::DrawOffered);
+    update.draw_offered_by = Some(color_to_string(color));
+    hub.publish(game_id, &update).await;
+
+    Ok(board_info(&game, clock, Some(color)))
+}
+
+#[post("/api/offer_draw", wrap = "from_fn(require_session)", wrap = "from_fn(mark_deprecated)")]
+async fn offer_draw(offer_draw_info: web::Json<GameActionInfo>, player: web::ReqData<Player>, hub: web::Data<GameHub>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(propose_draw(&pool, &offer_draw_info.game_id, &player, &hub).await?))
+}
+
+/*
+    Accepts or declines the draw the opponent offered.
+*/
+async fn answer_draw(pool: &DbPool, game_id: &str, accept: bool, player: &Player, hub: &GameHub) -> Result<BoardInfoResponse, ApiError> {
+    let mut conn = pool.get()?;
+
+    let (transaction, mut game, clock, color) = load_game_for_action(&mut conn, game_id, &player.name, hub).await?;
+
+    match get_draw_offer(&transaction, game_id) {
+        Ok(Some(offered_by)) if offered_by == color.opponent() => {},
+        Ok(_) => return Err(ApiError::NoDrawOffered),
+        Err(err) => return Err(ApiError::Database(err)),
+    }
+
+    let event = if accept {
+        game.agree_draw();
+        GameEvent::GameOver
     } else {
-        return Err(actix_web::error::ErrorInternalServerError("No game found"));
+        GameEvent::DrawDeclined
+    };
+
+    if game.is_over() {
+        if !end_game(&transaction, game_id, &game)? {
+            return Err(ApiError::StaleMove);
+        }
+    } else {
+        clear_draw_offer(&transaction, game_id)?;
     }
 
-    let game_result = Game::from_string(chfen, difficulty);
+    transaction.commit()?;
+
+    hub.publish(game_id, &state_update(&game, clock, event)).await;
 
-    if game_result.is_err() {
-        return Err(actix_web::error::ErrorInternalServerError("Error parsing FEN"));
+    Ok(board_info(&game, clock, None))
+}
+
+#[post("/api/respond_draw", wrap = "from_fn(require_session)", wrap = "from_fn(mark_deprecated)")]
+async fn respond_draw(respond_draw_info: web::Json<RespondDrawInfo>, player: web::ReqData<Player>, hub: web::Data<GameHub>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(answer_draw(&pool, &respond_draw_info.game_id, respond_draw_info.accept, &player, &hub).await?))
+}
+
+/*
+    Loads a game to be read, with its clock. A game whose side to move ran out of time is ended on time first,
+    under the write lock of the database like in load_game_for_action, and its watchers are told.
+    Other reads don't write, or wait for the write lock.
+*/
+async fn load_game_for_reading(conn: &mut Connection, game_id: &str, hub: &GameHub) -> Result<(Game, Option<Clock>), ApiError> {
+    let game = load_game(conn, game_id)?;
+    let clock = load_clock(conn, game_id)?;
+    let now = now_ms();
+
+    if !matches!(&clock, Some(clock) if !game.is_over() && clock.is_flagged(game.current_player, now)) {
+        return Ok((game, clock));
     }
 
-    let game = game_result.unwrap();
-    let legal_moves_result = get_legal_moves(&game.board, legal_moves_info.x, legal_moves_info.y);
+    let transaction = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
 
-    if legal_moves_result.is_err() {
-        return Err(actix_web::error::ErrorInternalServerError("Error getting legal moves"));
+    let mut game = load_game(&transaction, game_id)?;
+    let clock = load_clock(&transaction, game_id)?;
+
+    if check_flag(&transaction, game_id, &mut game, &clock, now)? {
+        transaction.commit()?;
+        hub.publish(game_id, &state_update(&game, clock, GameEvent::GameOver)).await;
     }
 
-    return Ok(format!("{:?}",legal_moves_result.unwrap()))
+    Ok((game, clock))
+}
+
+async fn load_board_info(pool: &DbPool, game_id: &str, req: &HttpRequest, viewer: &ViewerQuery, hub: &GameHub) -> Result<BoardInfoResponse, ApiError> {
+    let mut conn = pool.get()?;
+
+    check_viewer(&conn, game_id, req, viewer.share_token.as_deref(), None)?;
+
+    let (game, clock) = load_game_for_reading(&mut conn, game_id, hub).await?;
+
+    let draw_offer = get_draw_offer(&conn, game_id)?;
+
+    Ok(board_info(&game, clock, draw_offer))
+}
+
+#[post("/api/get_board", wrap = "from_fn(mark_deprecated)")]
+async fn get_board(req: HttpRequest, get_board_info: web::Json<GetBoardInfo>, hub: web::Data<GameHub>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let viewer = ViewerQuery {
+        share_token: get_board_info.share_token.clone(),
+    };
+
+    Ok(HttpResponse::Ok().json(load_board_info(&pool, &get_board_info.game_id, &req, &viewer, &hub).await?))
+}
+
+/*
+    The moves of the game so far, the game can be rebuilt by replaying them.
+*/
+fn list_moves(pool: &DbPool, game_id: &str, req: &HttpRequest, viewer: &ViewerQuery) -> Result<Vec<RecordedMove>, ApiError> {
+    let conn = pool.get()?;
+
+    check_viewer(&conn, game_id, req, viewer.share_token.as_deref(), None)?;
+
+    // Fails with GameNotFound for unknown games, instead of answering with no moves
+    load_game(&conn, game_id)?;
+
+    Ok(load_moves(&conn, game_id)?)
 }
 
-#[post("/api/get_games")]
-async fn get_games(legal_moves_info: web::Json<GetGamesInfo>) -> Result<HttpResponse, actix_web::error::Error> {
-    let conn = Connection::open(DB_NAME).expect(&format!("Failed database connection to {}",DB_NAME).to_owned());
+/*
+    Writes the game as a record, with the players and the date of the record it was imported from, if it was.
+    Games from before their moves were stored can't be written, their first moves are lost.
+*/
+async fn export_record(pool: &DbPool, game_id: &str, req: &HttpRequest, viewer: &ViewerQuery, hub: &GameHub) -> Result<String, ApiError> {
+    let mut conn = pool.get()?;
 
-    let statement_result = conn.prepare("SELECT id FROM games WHERE player_name=?1");
+    check_viewer(&conn, game_id, req, viewer.share_token.as_deref(), None)?;
 
-    if statement_result.is_err() {
-        return Err(actix_web::error::ErrorInternalServerError("SQL error"));
+    let (game, _) = load_game_for_reading(&mut conn, game_id, hub).await?;
+
+    let moves = load_moves(&conn, game_id)?;
+    if moves.len() != game.ply as usize || moves.iter().zip(1..).any(|(recorded, ply)| recorded.ply != ply) {
+        return Err(ApiError::IncompleteMoveHistory);
     }
 
-    let mut statement = statement_result.unwrap();
+    let imported = conn.query_row(
+        "SELECT white, black, date FROM imported_games WHERE game_id=?1",
+        rusqlite::params![game_id],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    );
 
-    let rows_result = statement.query_map(rusqlite::params![legal_moves_info.player_name], |row| {
-        row.get::<usize, String>(0)
-    });
+    let (white, black, date) = match imported {
+        Ok(imported) => imported,
+        Err(rusqlite::Error::QueryReturnedNoRows) => {
+            let created_at: Option<u64> = conn.query_row(
+                "SELECT created_at FROM games WHERE id=?1",
+                rusqlite::params![game_id],
+                |row| row.get(0),
+            )?;
+
+            (
+                get_rated_name(&conn, game_id, &game, Color::White)?,
+                get_rated_name(&conn, game_id, &game, Color::Black)?,
+                created_at.map(format_date),
+            )
+        },
+        Err(err) => return Err(ApiError::Database(err)),
+    };
+
+    let record = GameRecord {
+        white,
+        black,
+        date,
+        result: RecordResult::of_game(&game),
+        termination: game.end_reason,
+        moves: moves.iter().map(|recorded| RecordMove {
+            played: recorded.notation,
+            captures: recorded.captures.iter().filter_map(|captured| Square::from_coords(*captured)).collect(),
+        }).collect(),
+    };
 
-    if rows_result.is_err() {
-        return Err(actix_web::error::ErrorInternalServerError("Database query error"));
+    Ok(record.to_string())
+}
+
+/*
+    Stores the game of a record as a game of the player, who plays both of its sides from where the record ends.
+    Imported games aren't rated, whoever played them.
+*/
+fn import_record(pool: &DbPool, text: &str, player: &Player) -> Result<String, ApiError> {
+    let record = GameRecord::parse(text).map_err(ApiError::InvalidRecord)?;
+    let (game, captures) = record.replay().map_err(ApiError::InvalidRecord)?;
+
+    let mut conn = pool.get()?;
+
+    let game_id = Uuid::new_v4().to_string();
+    let game_state = game.to_string().map_err(ApiError::Internal)?;
+    let now = now_ms();
+
+    let transaction = conn.transaction()?;
+
+    transaction.execute(
+        "INSERT INTO games (id, game_state, player_name, winner, bot_difficulty, ply, created_at, updated_at)
+            VALUES (?1, ?2, ?3, ?4, 1, ?5, ?6, ?6)",
+        rusqlite::params![game_id, game_state, player.name, result_to_string(&game), game.ply, now],
+    )?;
+
+    for color in [Color::White, Color::Black] {
+        transaction.execute(
+            "INSERT INTO game_players (game_id, color, player_name) VALUES (?1, ?2, ?3)",
+            rusqlite::params![game_id, color_to_string(color), player.name],
+        )?;
+    }
+
+    // Records start from the starting position, where Black moves first
+    for (index, (played, captured)) in record.moves.iter().zip(&captures).enumerate() {
+        let color = if index % 2 == 0 { Color::Black } else { Color::White };
+        record_move(&transaction, &game_id, color, played.played.from.coords(), played.played.to.coords(), captured, now)?;
     }
 
-    let rows: Vec<String> = rows_result.unwrap().map(|r| r.unwrap()).collect();
+    transaction.execute(
+        "INSERT INTO imported_games (game_id, white, black, date) VALUES (?1, ?2, ?3, ?4)",
+        rusqlite::params![game_id, record.white, record.black, record.date],
+    )?;
+
+    if game.is_over() {
+        save_result(&transaction, &game_id, &game)?;
+    }
 
-    Ok(HttpResponse::Ok().json(rows))
+    transaction.commit()?;
+
+    Ok(game_id)
 }
 
-#[post("/api/get_board")]
-async fn get_board(get_board_info: web::Json<GetBoardInfo>) -> Result<HttpResponse, actix_web::error::Error> {
-    let conn = Connection::open(DB_NAME).expect(&format!("Failed database connection to {}",DB_NAME).to_owned());
+/*
+    Opens a WebSocket that pushes every move, bot reply and the result of the game.
+    The same callers as for get_board can watch it.
+*/
+async fn open_live_updates(pool: &DbPool, req: &HttpRequest, body: web::Payload, game_id: String, viewer: &LiveViewerQuery, hub: &GameHub) -> Result<HttpResponse, actix_web::error::Error> {
+    let mut conn = pool.get().map_err(ApiError::from)?;
+
+    check_viewer(&conn, &game_id, req, viewer.share_token.as_deref(), viewer.session_token.as_deref())?;
+
+    let (game, clock) = load_game_for_reading(&mut conn, &game_id, hub).await?;
 
-    let statement_result = conn.prepare("SELECT * FROM games WHERE id=?1");
+    let (response, mut session, mut msg_stream) = actix_ws::handle(req, body)?;
 
-    if statement_result.is_err() {
-        return Err(actix_web::error::ErrorInternalServerError("SQL error"));
+    let state = state_update(&game, clock, GameEvent::State);
+
+    if session.text(serde_json::to_string(&state).unwrap()).await.is_err() {
+        return Ok(response);
     }
 
-    let mut statement = statement_result.unwrap();
+    hub.subscribe(&game_id, session.clone());
+
+    actix_web::rt::spawn(async move {
+        while let Some(Ok(msg)) = msg_stream.recv().await {
+            let result = match msg {
+                actix_ws::Message::Ping(bytes) => session.pong(&bytes).await,
+                actix_ws::Message::Close(reason) => {
+                    let _ = session.close(reason).await;
+                    return;
+                },
+                _ => Ok(()),
+            };
+
+            if result.is_err() {
+                return;
+            }
+        }
+    });
 
-    let rows_result = statement.query(rusqlite::params![get_board_info.game_id]);
+    Ok(response)
+}
 
-    if rows_result.is_err() {
-        return Err(actix_web::error::ErrorInternalServerError("Database query error"));
+#[get("/api/live/{game_id}", wrap = "from_fn(mark_deprecated)")]
+async fn live_updates(req: HttpRequest, body: web::Payload, path: web::Path<String>, viewer: web::Query<LiveViewerQuery>, hub: web::Data<GameHub>, pool: web::Data<DbPool>) -> Result<HttpResponse, actix_web::error::Error> {
+    open_live_updates(&pool, &req, body, path.into_inner(), &viewer, &hub).await
+}
+
+/*
+    Only the players of a game can see and change its share links.
+*/
+fn check_game_player(conn: &Connection, game_id: &str, player: &Player) -> Result<(), ApiError> {
+    load_game(conn, game_id)?;
+
+    if !is_game_player(conn, game_id, &player.name)? {
+        return Err(ApiError::GameNotFound);
     }
 
-    let mut rows = rows_result.unwrap();
+    Ok(())
+}
 
-    let chfen: String;
-    let winner: String;
+fn share_game(pool: &DbPool, game_id: &str, player: &Player) -> Result<ShareLink, ApiError> {
+    let conn = pool.get()?;
 
-    if let Some(row) = rows.next().transpose() {
-        if row.is_err() {
-            return Err(actix_web::error::ErrorInternalServerError("No game found"));
-        }
-        let row_data = row.unwrap();
-        chfen = row_data.get("game_state").unwrap();
-        winner = row_data.get("winner").unwrap();
-    } else {
-        return Err(actix_web::error::ErrorInternalServerError("No game found"));
+    check_game_player(&conn, game_id, player)?;
+
+    Ok(sharing::create_share_link(&conn, game_id, &player.name, now_ms())?)
+}
+
+fn list_share_links(pool: &DbPool, game_id: &str, player: &Player) -> Result<Vec<ShareLink>, ApiError> {
+    let conn = pool.get()?;
+
+    check_game_player(&conn, game_id, player)?;
+
+    Ok(sharing::list_share_links(&conn, game_id)?)
+}
+
+fn revoke_share_link(pool: &DbPool, game_id: &str, token: &str, player: &Player) -> Result<(), ApiError> {
+    let conn = pool.get()?;
+
+    check_game_player(&conn, game_id, player)?;
+
+    if !sharing::revoke_share_link(&conn, game_id, token)? {
+        return Err(ApiError::ShareLinkNotFound);
     }
 
-    Ok(HttpResponse::Ok().json(BoardInfoResponse {
-        fen: chfen,
-        winner: winner,
-    }))
+    Ok(())
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok(); // This line loads the environment variables
 
-    println!("Connecting to database...");
-    let conn = Connection::open(DB_NAME).expect(&format!("Failed database connection to {}",DB_NAME).to_owned());
-    println!("Connected to {}",DB_NAME);
-
     println!("Loading .env variables...");
 
     let web_server_ip: &str = &std::env::var("WEB_SERVER_IP").expect("WEB_SERVER_IP missing from .env");
     let web_server_port: u16 = std::env::var("WEB_SERVER_PORT").expect("WEB_SERVER_PORT missing from .env").parse::<u16>().unwrap();
+    let database_path = std::env::var("DATABASE_PATH").unwrap_or_else(|_| DEFAULT_DB_PATH.to_string());
+    // Unfinished games nobody touched for this long are deleted
+    let abandoned_game_days: u64 = std::env::var("ABANDONED_GAME_DAYS")
+        .map(|days| days.parse::<u64>().expect("ABANDONED_GAME_DAYS must be a number of days"))
+        .unwrap_or(cleanup::DEFAULT_ABANDONED_GAME_DAYS);
+    let cleanup_interval_minutes: u64 = std::env::var("CLEANUP_INTERVAL_MINUTES")
+        .map(|minutes| minutes.parse::<u64>().expect("CLEANUP_INTERVAL_MINUTES must be a number of minutes"))
+        .unwrap_or(cleanup::DEFAULT_CLEANUP_INTERVAL_MINUTES);
+
+    println!("Connecting to database...");
+    let pool = open_pool(&database_path).map_err(std::io::Error::other)?;
+    let mut conn = pool.get().map_err(std::io::Error::other)?;
+    println!("Connected to {}", database_path);
+
+    let schema_version = migrate(&mut conn).map_err(std::io::Error::other)?;
+    println!("Database schema is at version {}", schema_version);
+
+    let hub = web::Data::new(GameHub::default());
+
+    // Bot moves that were still being searched when the server stopped need to be restarted
+    let mut unfinished_statement = conn.prepare("SELECT id, game_state, bot_difficulty FROM games WHERE winner='x'")
+        .map_err(std::io::Error::other)?;
+    let unfinished_games = unfinished_statement.query_map([], |row| {
+        Ok((row.get::<&str, String>("id")?, row.get::<&str, String>("game_state")?, row.get::<&str, u8>("bot_difficulty")?))
+    }).map_err(std::io::Error::other)?;
+
+    for (game_id, chfen, difficulty) in unfinished_games.flatten() {
+        if let Ok(game) = Game::from_string(chfen, difficulty) {
+            if game.is_bot_turn() {
+                spawn_bot_move(game_id, pool.clone(), hub.clone());
+            }
+        }
+    }
+
+    drop(unfinished_statement);
+    drop(conn);
+
+    cleanup::spawn_cleanup(
+        pool.clone(),
+        hub.clone(),
+        Duration::from_secs(cleanup_interval_minutes.max(1) * 60),
+        Duration::from_secs(abandoned_game_days * 24 * 60 * 60),
+    );
 
     println!("Starting web server at http://{}:{}/", web_server_ip, web_server_port);
 
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS games (
-            id TEXT PRIMARY KEY UNIQUE,
-            game_state TEXT,
-            player_name TEXT,
-            winner TEXT,
-            bot_difficulty INTEGER
-        )",
-        [],
-    ).expect("Failed to create table 'games'");
-
-    HttpServer::new(|| {
+    let pool = web::Data::new(pool);
+
+    HttpServer::new(move || {
         App::new()
             .wrap(Cors::permissive())
+            .app_data(hub.clone())
+            .app_data(pool.clone())
+            .app_data(web::JsonConfig::default().error_handler(|err, _req| {
+                ApiError::InvalidRequest(err.to_string()).into()
+            }))
+            .app_data(web::QueryConfig::default().error_handler(|err, _req| {
+                ApiError::InvalidRequest(err.to_string()).into()
+            }))
+            .configure(api_v1::configure)
             .service(hello)
+            .service(register)
+            .service(login)
+            .service(logout)
             .service(new_game)
             .service(make_move)
             .service(legal_moves)
             .service(get_games)
             .service(get_board)
+            .service(new_online_game)
+            .service(join_game)
+            .service(resign)
+            .service(offer_draw)
+            .service(respond_draw)
+            .service(live_updates)
     })
         .bind((web_server_ip, web_server_port))?
         .run()
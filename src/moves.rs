@@ -0,0 +1,163 @@
+use rusqlite::{Connection, Row};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::game::{Game, board::Color, notation::{Move, Square}};
+
+/*
+    A move as it is stored in the moves table. Plies are numbered from 1, in the order the moves were played.
+    Games from before moves were stored only have the moves played since then.
+*/
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug, ToSchema)]
+pub struct RecordedMove {
+    pub ply: u32,
+    pub from: (usize, usize),
+    pub to: (usize, usize),
+    // The same move in tafl notation
+    pub notation: Move,
+    pub captures: Vec<(usize, usize)>,
+    pub played_at_ms: u64,
+    // The side that played the move, "w" or "b". Not known for moves from before it was stored.
+    pub color: Option<String>,
+}
+
+/*
+    Stores the next move of the game, played by 'color', and returns its ply.
+    Should be called in the same transaction that stores the new state of the game.
+*/
+pub fn record_move(
+    conn: &Connection,
+    game_id: &str,
+    color: Color,
+    from: (usize, usize),
+    to: (usize, usize),
+    captures: &[(usize, usize)],
+    played_at_ms: u64,
+) -> rusqlite::Result<u32> {
+    let ply: u32 = conn.query_row(
+        "SELECT COALESCE(MAX(ply), 0) + 1 FROM moves WHERE game_id=?1",
+        rusqlite::params![game_id],
+        |row| row.get(0),
+    )?;
+
+    conn.execute(
+        "INSERT INTO moves (game_id, ply, from_x, from_y, to_x, to_y, captures, played_at, color)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        rusqlite::params![
+            game_id,
+            ply,
+            from.0,
+            from.1,
+            to.0,
+            to.1,
+            serde_json::to_string(captures).unwrap(),
+            played_at_ms,
+            if color == Color::White { "w" } else { "b" },
+        ],
+    )?;
+
+    Ok(ply)
+}
+
+fn move_from_row(row: &Row) -> rusqlite::Result<RecordedMove> {
+    let from = Square { x: row.get("from_x")?, y: row.get("from_y")? };
+    let to = Square { x: row.get("to_x")?, y: row.get("to_y")? };
+
+    Ok(RecordedMove {
+        ply: row.get("ply")?,
+        from: from.coords(),
+        to: to.coords(),
+        notation: Move { from, to },
+        captures: serde_json::from_str(&row.get::<&str, String>("captures")?).unwrap_or_default(),
+        played_at_ms: row.get("played_at")?,
+        color: row.get("color")?,
+    })
+}
+
+pub fn load_moves(conn: &Connection, game_id: &str) -> rusqlite::Result<Vec<RecordedMove>> {
+    let mut statement = conn.prepare(
+        "SELECT ply, from_x, from_y, to_x, to_y, captures, played_at, color FROM moves
+            WHERE game_id=?1 ORDER BY ply"
+    )?;
+
+    let moves = statement.query_map(rusqlite::params![game_id], move_from_row)?
+        .collect::<rusqlite::Result<Vec<RecordedMove>>>()?;
+
+    Ok(moves)
+}
+
+pub fn load_move(conn: &Connection, game_id: &str, ply: u32) -> rusqlite::Result<Option<RecordedMove>> {
+    let result = conn.query_row(
+        "SELECT ply, from_x, from_y, to_x, to_y, captures, played_at, color FROM moves
+            WHERE game_id=?1 AND ply=?2",
+        rusqlite::params![game_id, ply],
+        move_from_row,
+    );
+
+    match result {
+        Ok(recorded_move) => Ok(Some(recorded_move)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/*
+    Rebuilds a game by playing the moves from the starting position.
+    Only the moves are replayed, results that didn't come from a move, like resigning, are not part of them.
+*/
+pub fn replay(bot_white: bool, bot_black: bool, bot_difficulty: u8, moves: &[RecordedMove]) -> Result<Game, String> {
+    let mut game = Game::starting_position(bot_white, bot_black, bot_difficulty);
+
+    for recorded_move in moves {
+        if let Err(err) = game.make_move(recorded_move.from.0, recorded_move.from.1, recorded_move.to.0, recorded_move.to.1) {
+            return Err(format!("Ply {} can't be replayed: {}", recorded_move.ply, err));
+        }
+    }
+
+    Ok(game)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::migrate;
+    use crate::game::after_move_eval::get_captured_coords;
+
+    #[test]
+    fn test_record_and_replay() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+
+        let mut game = Game::starting_position(false, false, 1);
+        let moves = [((0, 3), (1, 3)), ((3, 5), (3, 9)), ((3, 0), (3, 8))];
+
+        for (ply, (from, to)) in moves.iter().enumerate() {
+            let board_before = game.board;
+            let mover = game.current_player;
+            game.make_move(from.0, from.1, to.0, to.1).unwrap();
+            let captures = get_captured_coords(&board_before, &game.board, mover);
+
+            assert_eq!(record_move(&conn, "game", mover, *from, *to, &captures, 1_000).unwrap(), ply as u32 + 1);
+        }
+        record_move(&conn, "other game", Color::Black, (0, 3), (1, 3), &[], 1_000).unwrap();
+
+        let recorded = load_moves(&conn, "game").unwrap();
+        assert_eq!(recorded.len(), 3);
+        assert_eq!(recorded[2].from, (3, 0));
+        assert_eq!(recorded[2].to, (3, 8));
+        assert_eq!(recorded[2].captures, vec![(3, 9)]);
+        assert_eq!(recorded[1].color.as_deref(), Some("w"));
+        assert_eq!(recorded[2].color.as_deref(), Some("b"));
+        assert_eq!(load_move(&conn, "game", 3).unwrap().as_ref(), recorded.get(2));
+        assert_eq!(load_move(&conn, "game", 4).unwrap(), None);
+
+        let replayed = replay(false, false, 1, &recorded).unwrap();
+        assert_eq!(replayed.to_string(), game.to_string());
+        assert_eq!(replayed.ply, 3);
+
+        // A move that doesn't fit the game can't be replayed
+        let mut broken = recorded.clone();
+        broken[1].from = (0, 0);
+        assert!(replay(false, false, 1, &broken).is_err());
+    }
+}
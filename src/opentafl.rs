@@ -0,0 +1,541 @@
+use std::{
+    io::{self, BufRead, Write},
+    time::Duration,
+};
+
+use crate::game::{
+    Game, VARIANT,
+    after_move_eval::get_captured_coords,
+    board::{Board, Color, HasColor, Tile},
+    engine::BotPlayer,
+    notation::{Move, Square},
+    record::RecordMove,
+};
+
+// Without overtime, a move may use this share of the time left
+const MOVES_TO_GO: u32 = 30;
+
+// The answer to every command that can't be carried out
+const ERROR: &str = "error -1";
+
+/*
+    How much time the sides have left, as sent by the clock command.
+*/
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Clock {
+    pub attackers_ms: u64,
+    pub defenders_ms: u64,
+    // The length of an overtime period, and how many each side has left
+    pub overtime_ms: u64,
+    pub attackers_overtimes: u32,
+    pub defenders_overtimes: u32,
+}
+
+impl Clock {
+    /*
+        Parses the arguments of "clock <attackers ms> <defenders ms> <overtime ms> <attackers overtimes> <defenders overtimes>".
+        Games without overtime may leave out the last three.
+    */
+    pub fn parse(args: &[&str]) -> Result<Clock, String> {
+        let number = |index: usize| -> Result<u64, String> {
+            match args.get(index) {
+                Some(arg) => arg.parse().map_err(|_| format!("'{}' isn't a time or a count", arg)),
+                None => Ok(0),
+            }
+        };
+
+        if args.len() < 2 || args.len() > 5 {
+            return Err("The clock needs the time of both sides".to_string());
+        }
+
+        Ok(Clock {
+            attackers_ms: number(0)?,
+            defenders_ms: number(1)?,
+            overtime_ms: number(2)?,
+            attackers_overtimes: number(3)? as u32,
+            defenders_overtimes: number(4)? as u32,
+        })
+    }
+
+    /*
+        How long the side may think about its next move: a share of its main time, and most of an overtime period
+        if it has one left, as the period starts over after every move played within it.
+    */
+    pub fn budget(&self, color: Color) -> Duration {
+        let (left_ms, overtimes) = match color {
+            Color::Black => (self.attackers_ms, self.attackers_overtimes),
+            _ => (self.defenders_ms, self.defenders_overtimes),
+        };
+        let overtime_ms = if overtimes > 0 { self.overtime_ms * 3 / 4 } else { 0 };
+
+        Duration::from_millis((left_ms / u64::from(MOVES_TO_GO) + overtime_ms).max(1))
+    }
+}
+
+/*
+    Parses the name of a side. Black attacks and White defends.
+*/
+pub fn parse_side(str: &str) -> Option<Color> {
+    match str {
+        "attackers" => Some(Color::Black),
+        "defenders" => Some(Color::White),
+        _ => None,
+    }
+}
+
+/*
+    Parses a position in OpenTafl's notation, the rows from the top separated by slashes, like
+    "/3ttttt3/5t5/11/...". 't' is an attacker, 'T' a defender, 'K' the king, and numbers count empty squares.
+*/
+pub fn parse_position(str: &str) -> Result<Board, String> {
+    let rows: Vec<&str> = str.trim().trim_matches('/').split('/').collect();
+
+    if rows.len() != 11 {
+        return Err(format!("A position needs 11 rows, not {}", rows.len()));
+    }
+
+    let mut board = Board::new();
+
+    for (x, row) in rows.iter().enumerate() {
+        let mut y = 0;
+        let mut empty = 0;
+
+        for char in row.chars().chain(std::iter::once('/')) {
+            if let Some(digit) = char.to_digit(10) {
+                empty = empty * 10 + digit as usize;
+                continue;
+            }
+
+            // Empty squares keep the corner or the throne Board::new put there
+            y += empty;
+            empty = 0;
+
+            let tile = match char {
+                't' => Tile::Black,
+                'T' => Tile::White,
+                'K' => Tile::King,
+                '/' => break,
+                _ => return Err(format!("Unknown piece '{}'", char)),
+            };
+
+            if y > 10 {
+                return Err(format!("Row {} of the position has more than 11 squares", x + 1));
+            }
+
+            board.set_tile(tile, x, y);
+            y += 1;
+        }
+
+        if y != 11 {
+            return Err(format!("Row {} of the position doesn't have 11 squares", x + 1));
+        }
+    }
+
+    Ok(board)
+}
+
+/*
+    Writes the board in OpenTafl's notation, the opposite of parse_position.
+*/
+pub fn format_position(board: &Board) -> String {
+    let mut str = String::from("/");
+
+    for row in board.board.iter() {
+        let mut empty = 0;
+
+        for tile in row.iter() {
+            let piece = match tile {
+                Tile::Black => 't',
+                Tile::White => 'T',
+                Tile::King => 'K',
+                Tile::Empty | Tile::Corner | Tile::Throne => {
+                    empty += 1;
+                    continue;
+                },
+            };
+
+            if empty > 0 {
+                str.push_str(&empty.to_string());
+                empty = 0;
+            }
+            str.push(piece);
+        }
+
+        if empty > 0 {
+            str.push_str(&empty.to_string());
+        }
+        str.push('/');
+    }
+
+    str
+}
+
+// The rule options of Copenhagen that OpenTafl may send, and their values
+const COPENHAGEN_OPTIONS: [(&str, &str); 5] = [
+    // The attackers move first
+    ("atkf", "y"),
+    // The king escapes to the corners
+    ("esc", "c"),
+    // An edge fort wins for the defenders
+    ("efe", "y"),
+    // The king takes part in captures
+    ("ka", "y"),
+    // The king is captured on four sides
+    ("ks", "s"),
+];
+
+/*
+    Checks rules like "dim:11 name:Copenhagen atkf:y ... start:/3ttttt3/...". The board has to be 11 by 11,
+    the name has to be Copenhagen, and the other options, and the starting position if the rules have one,
+    have to be Copenhagen's. Options that aren't known may change the game, so they aren't accepted either.
+    Returns the starting position.
+*/
+pub fn check_rules(rules: &str) -> Result<Board, String> {
+    let starting_board = Game::starting_position(false, false, 1).board;
+    let mut board = starting_board;
+    let mut dimension = None;
+    let mut name = None;
+
+    for option in rules.split_whitespace() {
+        let (key, value) = option.split_once(':').ok_or_else(|| format!("Invalid rule option {}", option))?;
+
+        match key {
+            "dim" => dimension = Some(value),
+            "name" => name = Some(value),
+            "start" => board = parse_position(value)?,
+            _ => match COPENHAGEN_OPTIONS.iter().find(|(known, _)| *known == key) {
+                Some((_, expected)) if value != *expected => {
+                    return Err(format!("Copenhagen rules have {}:{}, not {}:{}", key, expected, key, value));
+                },
+                Some(_) => {},
+                None => return Err(format!("Unknown rule option {}", option)),
+            },
+        }
+    }
+
+    if dimension != Some("11") {
+        return Err(format!("Only 11x11 boards are known, not {}", dimension.unwrap_or("an unknown size")));
+    }
+
+    match name {
+        Some(name) if name.eq_ignore_ascii_case(VARIANT) => {},
+        _ => return Err(format!("Only Copenhagen rules are known, not {}", name.unwrap_or("rules without a name"))),
+    }
+
+    if board.to_string()? != starting_board.to_string()? {
+        return Err("Only the starting position of Copenhagen is known".to_string());
+    }
+
+    Ok(board)
+}
+
+/*
+    An engine playing over the OpenTafl Engine Protocol: OpenTafl, or another host, sends commands
+    line by line, and the engine answers some of them:
+
+        hello                               -> hello
+        rules <rules>                       the rules of the game, answered with an error unless they are Copenhagen's
+        position <position>                 the position to play from
+        side <attackers|defenders>          the side the engine plays
+        clock <ms> <ms> [<ms> <n> <n>]      the time both sides have left, see Clock
+        play <attackers|defenders>          -> move <move>, the engine's move for the side
+        opponent-move <move> [<position>]   the opponent's move, and the position after it
+        finish <result>                     the game is over
+        goodbye                             the engine quits
+
+    Moves are in the notation of the API, like "d1-d4", and the engine's moves list what they captured,
+    like "a4-c4xc5/d4". Commands that fail, or that aren't known, are answered with "error -1".
+*/
+pub struct EngineSession {
+    pub engine: BotPlayer,
+    pub game: Game,
+    pub side: Option<Color>,
+    pub clock: Option<Clock>,
+    rules_accepted: bool,
+    finished: bool,
+}
+
+impl EngineSession {
+    pub fn new(engine: BotPlayer) -> EngineSession {
+        EngineSession {
+            engine,
+            game: Game::starting_position(false, false, 1),
+            side: None,
+            clock: None,
+            rules_accepted: false,
+            finished: false,
+        }
+    }
+
+    /*
+        True once the host said goodbye.
+    */
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /*
+        Carries out a command and returns the lines to answer with.
+    */
+    pub fn handle(&mut self, line: &str) -> Result<Vec<String>, String> {
+        let mut args = line.split_whitespace();
+        let command = match args.next() {
+            Some(command) => command,
+            None => return Ok(Vec::new()),
+        };
+        let args: Vec<&str> = args.collect();
+        let side = || args.first().and_then(|side| parse_side(side)).ok_or_else(|| format!("'{}' needs attackers or defenders", command));
+
+        match command {
+            "hello" => return Ok(vec!["hello".to_string()]),
+            "rules" => {
+                self.rules_accepted = false;
+                self.game = Game::starting_position(false, false, 1);
+                self.game.board = check_rules(&args.join(" "))?;
+                self.rules_accepted = true;
+            },
+            "position" => self.set_position(args.first().ok_or("The position is missing")?)?,
+            "side" => self.side = Some(side()?),
+            "clock" => self.clock = Some(Clock::parse(&args)?),
+            "play" => return Ok(vec![format!("move {}", self.play(side()?)?)]),
+            "opponent-move" => {
+                let played = RecordMove::parse(args.first().ok_or("The move is missing")?)?.played;
+
+                match args.get(1) {
+                    // The host's position is the one that counts, the move only tells whose turn it is
+                    Some(position) => {
+                        let mover = self.game.board.get_tile(played.from.x, played.from.y)?.color();
+                        if mover == Color::None {
+                            return Err(format!("There is no piece on {}", played.from));
+                        }
+                        self.set_position(position)?;
+                        self.game.current_player = mover.opponent();
+                    },
+                    None => {
+                        self.check_rules_accepted()?;
+                        self.game.make_move(played.from.x, played.from.y, played.to.x, played.to.y)?;
+                    },
+                }
+            },
+            // The host keeps track of the result
+            "finish" => {},
+            "goodbye" => self.finished = true,
+            _ => return Err(format!("Unknown command '{}'", command)),
+        }
+
+        Ok(Vec::new())
+    }
+
+    fn check_rules_accepted(&self) -> Result<(), String> {
+        if self.rules_accepted { Ok(()) } else { Err("No rules the engine knows were sent".to_string()) }
+    }
+
+    fn set_position(&mut self, position: &str) -> Result<(), String> {
+        self.check_rules_accepted()?;
+
+        let current_player = self.game.current_player;
+        self.game = Game::starting_position(false, false, 1);
+        self.game.board = parse_position(position)?;
+        self.game.current_player = current_player;
+
+        Ok(())
+    }
+
+    /*
+        Chooses and plays the move of 'color', within the budget of the clock if there is one.
+    */
+    fn play(&mut self, color: Color) -> Result<RecordMove, String> {
+        self.check_rules_accepted()?;
+
+        if self.game.is_over() {
+            return Err("The game is over".to_string());
+        }
+
+        // Positions don't say whose turn it is, the host does
+        self.game.current_player = color;
+
+        let budget = self.clock.map(|clock| clock.budget(color));
+        let best_move = self.engine.choose_move(&self.game.board, color, budget)?;
+        let board_before = self.game.board;
+
+        self.game.make_move(best_move[0].0, best_move[0].1, best_move[1].0, best_move[1].1)?;
+
+        let captures = get_captured_coords(&board_before, &self.game.board, color);
+
+        Ok(RecordMove {
+            played: Move::from_coords(best_move[0], best_move[1]).ok_or("The engine left the board")?,
+            captures: captures.into_iter().filter_map(Square::from_coords).collect(),
+        })
+    }
+}
+
+/*
+    Reads commands from 'input' until the host says goodbye or closes it, and writes the answers to 'output'.
+    What went wrong with failed commands is written to 'log', as 'output' is read by the host.
+*/
+pub fn run(engine: BotPlayer, input: impl BufRead, output: &mut impl Write, log: &mut impl Write) -> io::Result<()> {
+    let mut session = EngineSession::new(engine);
+
+    for line in input.lines() {
+        let line = line?;
+
+        match session.handle(&line) {
+            Ok(replies) => {
+                for reply in replies {
+                    writeln!(output, "{}", reply)?;
+                }
+            },
+            Err(err) => {
+                writeln!(log, "{}: {}", line.trim(), err)?;
+                writeln!(output, "{}", ERROR)?;
+            },
+        }
+
+        // The host waits for the answer
+        output.flush()?;
+
+        if session.is_finished() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::engine::Engine;
+
+    const COPENHAGEN: &str = "rules dim:11 name:Copenhagen atkf:y esc:c ks:s start:/3ttttt3/5t5/11/t4T4t/t3TTT3t/tt1TTKTT1tt/t3TTT3t/t4T4t/11/5t5/3ttttt3/";
+
+    fn play_script(engine: BotPlayer, script: &[&str]) -> (Vec<String>, String) {
+        let mut output = Vec::new();
+        let mut log = Vec::new();
+        run(engine, script.join("\n").as_bytes(), &mut output, &mut log).unwrap();
+
+        let output = String::from_utf8(output).unwrap().lines().map(str::to_string).collect();
+        (output, String::from_utf8(log).unwrap())
+    }
+
+    #[test]
+    fn test_positions() {
+        let starting_board = Game::starting_position(false, false, 1).board;
+        let position = format_position(&starting_board);
+        assert_eq!(position, "/3ttttt3/5t5/11/t4T4t/t3TTT3t/tt1TTKTT1tt/t3TTT3t/t4T4t/11/5t5/3ttttt3/");
+        assert_eq!(format_position(&parse_position(&position).unwrap()), position);
+
+        // The throne is still there once the king left it
+        let board = parse_position("/11/11/11/11/11/11/11/11/11/11/K10/").unwrap();
+        assert_eq!(board.get_tile(5, 5), Ok(Tile::Throne));
+        assert_eq!(board.get_tile(10, 0), Ok(Tile::King));
+
+        for invalid in ["/11/11/", "/12/11/11/11/11/11/11/11/11/11/11/", "/10/11/11/11/11/11/11/11/11/11/11/", "/x10/11/11/11/11/11/11/11/11/11/11/", "/11t/11/11/11/11/11/11/11/11/11/11/"] {
+            assert!(parse_position(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_rules() {
+        assert!(check_rules(COPENHAGEN.trim_start_matches("rules ")).is_ok());
+        assert!(check_rules("dim:11 name:copenhagen efe:y ka:y").is_ok());
+        assert!(check_rules("dim:7 name:Brandubh").is_err());
+        assert!(check_rules("name:Copenhagen").is_err());
+        assert!(check_rules("dim:11").is_err());
+        assert!(check_rules("dim:11 name:Copenhagen start:/11/11/11/11/11/5K5/11/11/11/11/11/").is_err());
+
+        // Copenhagen's name with other rules
+        for option in ["esc:e", "ks:w", "ka:n", "atkf:n", "efe:n", "sw:n", "edge"] {
+            assert!(check_rules(&format!("dim:11 name:Copenhagen {}", option)).is_err(), "{}", option);
+        }
+    }
+
+    #[test]
+    fn test_clock() {
+        let clock = Clock::parse(&["300000", "60000", "10000", "3", "0"]).unwrap();
+        assert_eq!(clock.budget(Color::Black), Duration::from_millis(10000 + 7500));
+        assert_eq!(clock.budget(Color::White), Duration::from_millis(2000));
+        assert_eq!(Clock::parse(&["0", "0"]).unwrap().budget(Color::White), Duration::from_millis(1));
+        assert!(Clock::parse(&["300000"]).is_err());
+        assert!(Clock::parse(&["300000", "-1"]).is_err());
+    }
+
+    #[test]
+    fn test_engine_plays_both_sides() {
+        let engine = BotPlayer { engine: Engine::Minimax, difficulty: 2 };
+        let (output, log) = play_script(engine, &[
+            "hello",
+            COPENHAGEN,
+            "side attackers",
+            "clock 300000 300000 10000 3 3",
+            "play attackers",
+            "opponent-move f4-i4",
+            "play attackers",
+            "goodbye",
+            "play attackers",
+        ]);
+
+        // Nothing is read after goodbye
+        assert_eq!(output.len(), 3, "{}", log);
+        assert_eq!(output[0], "hello");
+
+        let mut game = Game::starting_position(false, false, 1);
+        for reply in [&output[1], "move f4-i4", &output[2]] {
+            let played = RecordMove::parse(reply.strip_prefix("move ").unwrap()).unwrap().played;
+            game.make_move(played.from.x, played.from.y, played.to.x, played.to.y).unwrap();
+        }
+        assert_eq!(game.ply, 3);
+
+        // As the defenders, the engine replies to the attackers' moves
+        let (output, log) = play_script(engine, &[
+            COPENHAGEN,
+            "side defenders",
+            "clock 60000 60000",
+            "opponent-move d1-d4",
+            "play defenders",
+            "opponent-move k5-j5",
+            "play defenders",
+            "goodbye",
+        ]);
+
+        assert_eq!(output.len(), 2, "{}", log);
+
+        let mut game = Game::starting_position(false, false, 1);
+        for reply in ["move d1-d4", &output[0], "move k5-j5", &output[1]] {
+            let played = RecordMove::parse(reply.strip_prefix("move ").unwrap()).unwrap().played;
+            game.make_move(played.from.x, played.from.y, played.to.x, played.to.y).unwrap();
+        }
+        assert_eq!(game.ply, 4);
+        assert_eq!(game.current_player, Color::Black);
+    }
+
+    #[test]
+    fn test_engine_follows_the_host() {
+        let engine = BotPlayer { engine: Engine::Random, difficulty: 1 };
+        let (output, log) = play_script(engine, &[
+            // Nothing can be played before the rules
+            "play defenders",
+            "rules dim:9",
+            "position /11/11/11/11/11/11/11/11/11/11/11/",
+            COPENHAGEN,
+            // The king next to the corner, with a single attacker on the board
+            "position /1K9/11/11/11/11/11/11/11/11/11/5t5/",
+            "play defenders",
+            "dance",
+        ]);
+
+        assert_eq!(output[..3], ["error -1", "error -1", "error -1"], "{}", log);
+        let played = RecordMove::parse(output[3].strip_prefix("move ").unwrap()).unwrap().played;
+        assert_eq!(played.from, "b11".parse().unwrap());
+        assert_eq!(output[4], "error -1");
+        assert!(log.contains("Unknown command 'dance'"));
+
+        // The host's position replaces the engine's after the opponent's move
+        let mut session = EngineSession::new(engine);
+        session.handle(COPENHAGEN).unwrap();
+        session.handle("opponent-move d1-d4 /3ttttt3/5t5/11/t4T4t/t3TTT3t/tt1TTKTT1tt/t3TTT3t/t2t1T4t/11/5t5/4tttt3/").unwrap();
+        assert_eq!(session.game.current_player, Color::White);
+        assert_eq!(session.game.board.get_tile(7, 3), Ok(Tile::Black));
+        assert!(session.handle("opponent-move e5-e6").is_err());
+    }
+}
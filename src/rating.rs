@@ -0,0 +1,311 @@
+use std::f64::consts::PI;
+
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::game::{board::Color, engine::BotPlayer};
+
+// Converts between the Glicko scale, which players see, and the Glicko-2 scale the updates are done in
+const GLICKO2_SCALE: f64 = 173.7178;
+const DEFAULT_RATING: f64 = 1500.0;
+const DEFAULT_DEVIATION: f64 = 350.0;
+const DEFAULT_VOLATILITY: f64 = 0.06;
+// How much the volatility may change, smaller values make ratings steadier
+const TAU: f64 = 0.5;
+const CONVERGENCE_TOLERANCE: f64 = 0.000001;
+
+/*
+    A Glicko-2 rating, on the Glicko scale. The deviation shrinks as the rating gets more certain.
+*/
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug, ToSchema)]
+pub struct Rating {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+}
+
+impl Default for Rating {
+    fn default() -> Rating {
+        Rating {
+            rating: DEFAULT_RATING,
+            deviation: DEFAULT_DEVIATION,
+            volatility: DEFAULT_VOLATILITY,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug, ToSchema)]
+pub struct RatingChange {
+    pub game_id: String,
+    #[serde(flatten)]
+    pub rating: Rating,
+    pub rated_at_ms: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug, ToSchema)]
+pub struct RatingHistory {
+    pub player_name: String,
+    #[serde(flatten)]
+    pub rating: Rating,
+    // Oldest first
+    pub history: Vec<RatingChange>,
+}
+
+fn g(phi: f64) -> f64 {
+    1.0 / (1.0 + 3.0 * phi * phi / (PI * PI)).sqrt()
+}
+
+fn expected_score(mu: f64, opponent_mu: f64, opponent_phi: f64) -> f64 {
+    1.0 / (1.0 + (-g(opponent_phi) * (mu - opponent_mu)).exp())
+}
+
+impl Rating {
+    /*
+        The rating after a rating period with the given results, which are the opponent's rating
+        before the game, and the score: 1 for a win, 0.5 for a draw and 0 for a loss.
+        Follows the steps of Glickman's "Example of the Glicko-2 system".
+    */
+    pub fn update(&self, results: &[(Rating, f64)]) -> Rating {
+        let mu = (self.rating - DEFAULT_RATING) / GLICKO2_SCALE;
+        let phi = self.deviation / GLICKO2_SCALE;
+
+        if results.is_empty() {
+            let deviation = (phi * phi + self.volatility * self.volatility).sqrt() * GLICKO2_SCALE;
+            return Rating { deviation, ..*self };
+        }
+
+        let mut variance_inverse = 0.0;
+        let mut score_sum = 0.0;
+
+        for (opponent, score) in results {
+            let opponent_mu = (opponent.rating - DEFAULT_RATING) / GLICKO2_SCALE;
+            let opponent_phi = opponent.deviation / GLICKO2_SCALE;
+            let expected = expected_score(mu, opponent_mu, opponent_phi);
+
+            variance_inverse += g(opponent_phi).powi(2) * expected * (1.0 - expected);
+            score_sum += g(opponent_phi) * (score - expected);
+        }
+
+        let v = 1.0 / variance_inverse;
+        let delta = v * score_sum;
+
+        // The new volatility is the root of f, found with the Illinois algorithm
+        let a = (self.volatility * self.volatility).ln();
+        let f = |x: f64| {
+            let ex = x.exp();
+            ex * (delta * delta - phi * phi - v - ex) / (2.0 * (phi * phi + v + ex).powi(2)) - (x - a) / (TAU * TAU)
+        };
+
+        let mut big_a = a;
+        let mut big_b = if delta * delta > phi * phi + v {
+            (delta * delta - phi * phi - v).ln()
+        } else {
+            let mut k = 1.0;
+            while f(a - k * TAU) < 0.0 {
+                k += 1.0;
+            }
+            a - k * TAU
+        };
+
+        let mut f_a = f(big_a);
+        let mut f_b = f(big_b);
+
+        while (big_b - big_a).abs() > CONVERGENCE_TOLERANCE {
+            let big_c = big_a + (big_a - big_b) * f_a / (f_b - f_a);
+            let f_c = f(big_c);
+
+            if f_c * f_b <= 0.0 {
+                big_a = big_b;
+                f_a = f_b;
+            } else {
+                f_a /= 2.0;
+            }
+
+            big_b = big_c;
+            f_b = f_c;
+        }
+
+        let volatility = (big_a / 2.0).exp();
+        let phi_star = (phi * phi + volatility * volatility).sqrt();
+        let new_phi = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / v).sqrt();
+        let new_mu = mu + new_phi * new_phi * score_sum;
+
+        Rating {
+            rating: new_mu * GLICKO2_SCALE + DEFAULT_RATING,
+            deviation: new_phi * GLICKO2_SCALE,
+            volatility,
+        }
+    }
+}
+
+/*
+    Bots are rated like players, every difficulty under its own name.
+    Nobody can register these names, see is_bot_name.
+*/
+pub fn bot_player_name(bot_difficulty: u8) -> String {
+    format!("Krabbi bot {}", bot_difficulty)
+}
+
+/*
+    The bots of the bot difficulties keep their names, other engines are named like "Krabbi minimax 3".
+*/
+pub fn engine_player_name(bot: &BotPlayer) -> String {
+    match bot.level() {
+        Some(level) => bot_player_name(level),
+        None => format!("Krabbi {} {}", bot.engine.as_str(), bot.difficulty),
+    }
+}
+
+pub fn is_bot_name(player_name: &str) -> bool {
+    player_name.starts_with("Krabbi ")
+}
+
+pub fn load_rating(conn: &Connection, player_name: &str) -> rusqlite::Result<Rating> {
+    let result = conn.query_row(
+        "SELECT rating, deviation, volatility FROM ratings WHERE player_name=?1",
+        rusqlite::params![player_name],
+        |row| Ok(Rating {
+            rating: row.get(0)?,
+            deviation: row.get(1)?,
+            volatility: row.get(2)?,
+        }),
+    );
+
+    match result {
+        Ok(rating) => Ok(rating),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(Rating::default()),
+        Err(err) => Err(err),
+    }
+}
+
+fn save_rating(conn: &Connection, player_name: &str, game_id: &str, rating: &Rating, now_ms: u64) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO ratings (player_name, rating, deviation, volatility) VALUES (?1, ?2, ?3, ?4)",
+        rusqlite::params![player_name, rating.rating, rating.deviation, rating.volatility],
+    )?;
+
+    conn.execute(
+        "INSERT INTO rating_history (player_name, game_id, rating, deviation, volatility, rated_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        rusqlite::params![player_name, game_id, rating.rating, rating.deviation, rating.volatility, now_ms],
+    )?;
+
+    Ok(())
+}
+
+/*
+    Updates the ratings of both sides of a finished game, every game is a rating period of its own.
+    The winner is Color::None for a draw. Games are only rated once, and games against yourself aren't rated.
+*/
+pub fn rate_game(conn: &Connection, game_id: &str, white: &str, black: &str, winner: Color, now_ms: u64) -> rusqlite::Result<()> {
+    if white == black {
+        return Ok(());
+    }
+
+    let already_rated: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM rating_history WHERE game_id=?1)",
+        rusqlite::params![game_id],
+        |row| row.get(0),
+    )?;
+
+    if already_rated {
+        return Ok(());
+    }
+
+    let white_score = match winner {
+        Color::White => 1.0,
+        Color::Black => 0.0,
+        Color::None => 0.5,
+    };
+
+    let white_rating = load_rating(conn, white)?;
+    let black_rating = load_rating(conn, black)?;
+
+    save_rating(conn, white, game_id, &white_rating.update(&[(black_rating, white_score)]), now_ms)?;
+    save_rating(conn, black, game_id, &black_rating.update(&[(white_rating, 1.0 - white_score)]), now_ms)?;
+
+    Ok(())
+}
+
+pub fn load_rating_history(conn: &Connection, player_name: &str) -> rusqlite::Result<RatingHistory> {
+    let mut statement = conn.prepare(
+        "SELECT game_id, rating, deviation, volatility, rated_at FROM rating_history
+            WHERE player_name=?1 ORDER BY rated_at, rowid"
+    )?;
+
+    let history = statement.query_map(rusqlite::params![player_name], |row| {
+        Ok(RatingChange {
+            game_id: row.get(0)?,
+            rating: Rating {
+                rating: row.get(1)?,
+                deviation: row.get(2)?,
+                volatility: row.get(3)?,
+            },
+            rated_at_ms: row.get(4)?,
+        })
+    })?.collect::<rusqlite::Result<Vec<RatingChange>>>()?;
+
+    Ok(RatingHistory {
+        player_name: player_name.to_string(),
+        rating: load_rating(conn, player_name)?,
+        history,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::migrate;
+
+    #[test]
+    fn test_glicko2_example() {
+        // The example from Glickman's paper
+        let player = Rating { rating: 1500.0, deviation: 200.0, volatility: 0.06 };
+        let results = [
+            (Rating { rating: 1400.0, deviation: 30.0, volatility: 0.06 }, 1.0),
+            (Rating { rating: 1550.0, deviation: 100.0, volatility: 0.06 }, 0.0),
+            (Rating { rating: 1700.0, deviation: 300.0, volatility: 0.06 }, 0.0),
+        ];
+
+        let updated = player.update(&results);
+        assert!((updated.rating - 1464.06).abs() < 0.01);
+        assert!((updated.deviation - 151.52).abs() < 0.01);
+        assert!((updated.volatility - 0.05999).abs() < 0.00001);
+
+        // Not playing makes the rating less certain
+        assert!(player.update(&[]).deviation > player.deviation);
+    }
+
+    #[test]
+    fn test_rate_game() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+
+        let bot = bot_player_name(2);
+        rate_game(&conn, "1", "alice", &bot, Color::White, 1_000).unwrap();
+        // Storing the result again doesn't rate the game twice
+        rate_game(&conn, "1", "alice", &bot, Color::White, 2_000).unwrap();
+        rate_game(&conn, "2", "alice", "alice", Color::White, 3_000).unwrap();
+
+        let alice = load_rating_history(&conn, "alice").unwrap();
+        assert!(alice.rating.rating > 1500.0);
+        assert!(alice.rating.deviation < 350.0);
+        assert_eq!(alice.history.len(), 1);
+        assert_eq!(alice.history[0].game_id, "1");
+
+        let bot_rating = load_rating(&conn, &bot).unwrap();
+        assert!(bot_rating.rating < 1500.0);
+        // Both started from the same rating, so they moved by the same amount
+        assert!((alice.rating.rating - 1500.0 - (1500.0 - bot_rating.rating)).abs() < 0.000001);
+
+        rate_game(&conn, "3", "alice", &bot, Color::None, 4_000).unwrap();
+        assert!(load_rating(&conn, "alice").unwrap().rating < alice.rating.rating);
+
+        assert!(is_bot_name(&bot));
+        assert_eq!(engine_player_name(&BotPlayer::from_level(2).unwrap()), bot);
+        assert_eq!(engine_player_name(&BotPlayer { engine: crate::game::engine::Engine::Minimax, difficulty: 3 }), "Krabbi minimax 3");
+        assert!(!is_bot_name("alice"));
+        assert_eq!(load_rating(&conn, "nobody").unwrap(), Rating::default());
+    }
+}
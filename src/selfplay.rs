@@ -0,0 +1,280 @@
+use std::{
+    collections::HashSet,
+    io::{self, Write},
+    sync::{atomic::{AtomicU32, Ordering}, mpsc},
+    thread,
+};
+
+use serde::Serialize;
+
+use crate::game::{
+    EndReason, Game,
+    ai::get_random_move,
+    board::Color,
+    engine::BotPlayer,
+};
+
+// Games of two deterministic bots would all be the same, so they start with a few random moves
+pub const DEFAULT_RANDOM_PLIES: u32 = 4;
+
+/*
+    Games between bots, played to collect positions to train or tune evaluations on.
+*/
+pub struct SelfPlay {
+    pub white: BotPlayer,
+    pub black: BotPlayer,
+    pub games: u32,
+    pub threads: usize,
+    // How many random moves every game starts with, these positions aren't recorded
+    pub random_plies: u32,
+    pub max_plies: u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    Jsonl,
+    Csv,
+}
+
+impl Format {
+    pub fn parse(str: &str) -> Option<Format> {
+        match str {
+            "jsonl" => Some(Format::Jsonl),
+            "csv" => Some(Format::Csv),
+            _ => None,
+        }
+    }
+}
+
+/*
+    A position of a self-play game, with the move the bot to move played in it.
+*/
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct TrainingPosition {
+    // position_hash of the position, as hex, so it survives JSON parsers that read numbers as doubles
+    pub hash: String,
+    // The board in the format of Board::to_string
+    pub board: String,
+    // "w" or "b"
+    pub side_to_move: String,
+    // The evaluation of the bot's search, positive is good for White. None for bots that don't search,
+    // and for evaluations that aren't finite, like a king that can escape
+    pub score: Option<f32>,
+    pub from: (usize, usize),
+    pub to: (usize, usize),
+    // The points White got in the game: 1 for a win, 0.5 for a draw, 0 for a loss
+    pub result: f64,
+}
+
+/*
+    A hash of the board and the side to move, the same on every run and every machine.
+    This is 64 bit FNV-1a of the position's string.
+*/
+pub fn position_hash(board: &str, side_to_move: &str) -> u64 {
+    board.bytes()
+        .chain(std::iter::once(b' '))
+        .chain(side_to_move.bytes())
+        .fold(0xcbf29ce484222325, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3))
+}
+
+/*
+    Plays a game between the bots and returns its positions, from the first one after the random moves.
+*/
+pub fn play_training_game(self_play: &SelfPlay) -> Result<Vec<TrainingPosition>, String> {
+    let mut game = Game::starting_position(true, true, 1);
+    let mut positions: Vec<TrainingPosition> = Vec::new();
+
+    while !game.is_over() {
+        if game.ply >= self_play.max_plies {
+            game.draw_by_move_limit();
+            break;
+        }
+
+        let color = game.current_player;
+        let bot = if color == Color::White { self_play.white } else { self_play.black };
+
+        let search = if game.ply < self_play.random_plies {
+            get_random_move(game.board, color).map(|random_move| (random_move, None))
+        } else {
+            bot.search(&game.board, color)
+        };
+
+        let (best_move, score) = match search {
+            Ok(search) => search,
+            Err(_) => {
+                // Like make_bot_move, a side without legal moves loses
+                game.board.winner = color.opponent();
+                game.end_reason = Some(EndReason::NoLegalMoves);
+                break;
+            },
+        };
+
+        if game.ply >= self_play.random_plies {
+            let board = game.board.to_string()?;
+            let side_to_move = if color == Color::White { "w" } else { "b" };
+
+            positions.push(TrainingPosition {
+                hash: format!("{:016x}", position_hash(&board, side_to_move)),
+                board,
+                side_to_move: side_to_move.to_string(),
+                score: score.filter(|score| score.is_finite()),
+                from: best_move[0],
+                to: best_move[1],
+                // Filled in once the game is over
+                result: 0.0,
+            });
+        }
+
+        game.make_move(best_move[0].0, best_move[0].1, best_move[1].0, best_move[1].1)?;
+    }
+
+    let result = match game.get_winner() {
+        Color::White => 1.0,
+        Color::Black => 0.0,
+        Color::None => 0.5,
+    };
+
+    for position in &mut positions {
+        position.result = result;
+    }
+
+    Ok(positions)
+}
+
+pub fn write_header(out: &mut impl Write, format: Format) -> io::Result<()> {
+    match format {
+        Format::Jsonl => Ok(()),
+        Format::Csv => writeln!(out, "hash,board,side_to_move,score,from_x,from_y,to_x,to_y,result"),
+    }
+}
+
+pub fn write_position(out: &mut impl Write, format: Format, position: &TrainingPosition) -> io::Result<()> {
+    match format {
+        Format::Jsonl => writeln!(out, "{}", serde_json::to_string(position)?),
+        Format::Csv => writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{}",
+            position.hash,
+            position.board,
+            position.side_to_move,
+            position.score.map(|score| score.to_string()).unwrap_or_default(),
+            position.from.0, position.from.1, position.to.0, position.to.1,
+            position.result,
+        ),
+    }
+}
+
+pub struct ExportReport {
+    pub games: u32,
+    pub positions: usize,
+    pub duplicates: usize,
+}
+
+/*
+    Plays the games on the threads and writes every position to 'out' as soon as its game is over.
+    Positions that were already written, by an earlier game or earlier in the same game, are skipped.
+*/
+pub fn export_training_data(self_play: &SelfPlay, format: Format, out: &mut impl Write) -> Result<ExportReport, String> {
+    let next_game = AtomicU32::new(0);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..self_play.threads.max(1) {
+            let sender = sender.clone();
+            let next_game = &next_game;
+
+            scope.spawn(move || {
+                while next_game.fetch_add(1, Ordering::Relaxed) < self_play.games {
+                    let game = play_training_game(self_play);
+                    let failed = game.is_err();
+
+                    if sender.send(game).is_err() || failed {
+                        break;
+                    }
+                }
+            });
+        }
+        // The receiver stops once every thread is done
+        drop(sender);
+
+        let mut seen = HashSet::new();
+        let mut report = ExportReport { games: 0, positions: 0, duplicates: 0 };
+
+        write_header(out, format).map_err(|err| err.to_string())?;
+
+        for game in receiver {
+            for position in game? {
+                if !seen.insert(position.hash.clone()) {
+                    report.duplicates += 1;
+                    continue;
+                }
+
+                write_position(out, format, &position).map_err(|err| err.to_string())?;
+                report.positions += 1;
+            }
+
+            report.games += 1;
+        }
+
+        out.flush().map_err(|err| err.to_string())?;
+
+        Ok(report)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::engine::Engine;
+
+    fn self_play(games: u32) -> SelfPlay {
+        SelfPlay {
+            white: BotPlayer { engine: Engine::Minimax, difficulty: 1 },
+            black: BotPlayer { engine: Engine::Random, difficulty: 1 },
+            games,
+            threads: 2,
+            random_plies: 2,
+            max_plies: 30,
+        }
+    }
+
+    #[test]
+    fn test_position_hash() {
+        assert_eq!(position_hash("", ""), position_hash("", ""));
+        assert_ne!(position_hash("board", "w"), position_hash("board", "b"));
+        // FNV-1a of " "
+        assert_eq!(position_hash("", ""), 0xaf639d4c8601817f);
+    }
+
+    #[test]
+    fn test_play_training_game() {
+        let positions = play_training_game(&self_play(1)).unwrap();
+        assert!(!positions.is_empty() && positions.len() <= 28);
+
+        // The random moves aren't recorded, so Black is to move in the first position
+        assert_eq!(positions[0].side_to_move, "b");
+        assert!(positions.iter().all(|position| position.result == positions[0].result));
+        // Only minimax searches
+        assert!(positions.iter().filter(|position| position.side_to_move == "b").all(|position| position.score.is_none()));
+    }
+
+    #[test]
+    fn test_export_training_data() {
+        let mut csv = Vec::new();
+        let report = export_training_data(&self_play(3), Format::Csv, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+
+        assert_eq!(report.games, 3);
+        assert_eq!(csv.lines().count(), report.positions + 1);
+        assert!(csv.starts_with("hash,board,side_to_move,"));
+
+        let hashes: HashSet<&str> = csv.lines().skip(1).map(|line| line.split(',').next().unwrap()).collect();
+        assert_eq!(hashes.len(), report.positions);
+
+        let mut jsonl = Vec::new();
+        let report = export_training_data(&self_play(1), Format::Jsonl, &mut jsonl).unwrap();
+        let first: serde_json::Value = serde_json::from_slice(jsonl.split(|byte| *byte == b'\n').next().unwrap()).unwrap();
+        assert_eq!(String::from_utf8(jsonl).unwrap().lines().count(), report.positions);
+        assert!(first["hash"].is_string() && first["from"].is_array());
+    }
+}
@@ -0,0 +1,125 @@
+use rand::{distributions::Alphanumeric, Rng};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+const SHARE_TOKEN_LENGTH: usize = 32;
+
+/*
+    A read-only link to a game. Anyone with the token can watch the game, until a player revokes it.
+*/
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug, ToSchema)]
+pub struct ShareLink {
+    pub token: String,
+    pub game_id: String,
+    pub created_by: String,
+    pub created_at_ms: u64,
+}
+
+/*
+    How a caller that isn't sending an Authorization header proves it may watch a game.
+*/
+#[derive(Deserialize, Serialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ViewerQuery {
+    // Token of a share link of the game
+    pub share_token: Option<String>,
+}
+
+/*
+    The same for the WebSocket of a game. Browsers can't send headers when opening a WebSocket,
+    so players may pass their session token here instead. Nothing else takes the session token from the URL,
+    where it would end up in access logs and Referer headers.
+*/
+#[derive(Deserialize, Serialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LiveViewerQuery {
+    // Token of a share link of the game
+    pub share_token: Option<String>,
+    // Session token of one of the game's players
+    pub session_token: Option<String>,
+}
+
+pub fn create_share_link(conn: &Connection, game_id: &str, player_name: &str, now_ms: u64) -> rusqlite::Result<ShareLink> {
+    let token: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(SHARE_TOKEN_LENGTH)
+        .map(char::from)
+        .collect();
+
+    conn.execute(
+        "INSERT INTO share_links (token, game_id, created_by, created_at) VALUES (?1, ?2, ?3, ?4)",
+        rusqlite::params![token, game_id, player_name, now_ms],
+    )?;
+
+    Ok(ShareLink {
+        token,
+        game_id: game_id.to_string(),
+        created_by: player_name.to_string(),
+        created_at_ms: now_ms,
+    })
+}
+
+pub fn list_share_links(conn: &Connection, game_id: &str) -> rusqlite::Result<Vec<ShareLink>> {
+    let mut statement = conn.prepare(
+        "SELECT token, game_id, created_by, created_at FROM share_links WHERE game_id=?1 ORDER BY created_at, token"
+    )?;
+
+    let share_links = statement.query_map(rusqlite::params![game_id], |row| {
+        Ok(ShareLink {
+            token: row.get(0)?,
+            game_id: row.get(1)?,
+            created_by: row.get(2)?,
+            created_at_ms: row.get(3)?,
+        })
+    })?.collect::<rusqlite::Result<Vec<ShareLink>>>()?;
+
+    Ok(share_links)
+}
+
+/*
+    Revokes the share link, and returns false if the game has no link with the token.
+*/
+pub fn revoke_share_link(conn: &Connection, game_id: &str, token: &str) -> rusqlite::Result<bool> {
+    let deleted = conn.execute(
+        "DELETE FROM share_links WHERE game_id=?1 AND token=?2",
+        rusqlite::params![game_id, token],
+    )?;
+
+    Ok(deleted > 0)
+}
+
+pub fn is_valid_share_token(conn: &Connection, game_id: &str, token: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM share_links WHERE game_id=?1 AND token=?2)",
+        rusqlite::params![game_id, token],
+        |row| row.get(0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::migrate;
+
+    #[test]
+    fn test_share_links() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+
+        let first = create_share_link(&conn, "game", "alice", 1_000).unwrap();
+        let second = create_share_link(&conn, "game", "bob", 2_000).unwrap();
+        assert_eq!(first.token.len(), SHARE_TOKEN_LENGTH);
+        assert_ne!(first.token, second.token);
+        assert_eq!(list_share_links(&conn, "game").unwrap(), vec![first.clone(), second.clone()]);
+
+        assert!(is_valid_share_token(&conn, "game", &first.token).unwrap());
+        // Tokens only open the game they were made for
+        assert!(!is_valid_share_token(&conn, "other game", &first.token).unwrap());
+
+        assert!(revoke_share_link(&conn, "game", &first.token).unwrap());
+        assert!(!revoke_share_link(&conn, "game", &first.token).unwrap());
+        assert!(!is_valid_share_token(&conn, "game", &first.token).unwrap());
+        assert_eq!(list_share_links(&conn, "game").unwrap(), vec![second]);
+    }
+}
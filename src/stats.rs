@@ -0,0 +1,382 @@
+use std::collections::{BTreeMap, HashMap};
+
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::rating::Rating;
+
+// Players with fewer finished games than this aren't ranked by default
+pub const DEFAULT_MIN_GAMES: u32 = 1;
+pub const DEFAULT_LEADERBOARD_SIZE: u32 = 50;
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug, Default, ToSchema)]
+pub struct Record {
+    pub played: u32,
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+}
+
+impl Record {
+    fn add(&mut self, outcome: Outcome) {
+        self.played += 1;
+        match outcome {
+            Outcome::Win => self.wins += 1,
+            Outcome::Loss => self.losses += 1,
+            Outcome::Draw => self.draws += 1,
+        }
+    }
+
+    /*
+        The share of the points won, a draw is worth half a win.
+    */
+    pub fn win_rate(&self) -> f64 {
+        if self.played == 0 {
+            return 0.0;
+        }
+
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.played as f64
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug, ToSchema)]
+pub struct BotRecord {
+    pub bot_difficulty: u8,
+    #[serde(flatten)]
+    pub record: Record,
+}
+
+/*
+    The results of the finished games of a player. Games the player played against themselves don't count.
+    wins_by_reason is keyed by the result_reason of the games, like "king_escaped".
+*/
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug, ToSchema)]
+pub struct PlayerStats {
+    pub player_name: String,
+    pub overall: Record,
+    pub as_white: Record,
+    pub as_black: Record,
+    pub wins_by_reason: BTreeMap<String, u32>,
+    pub against_humans: Record,
+    pub against_bots: Vec<BotRecord>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug, Default, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LeaderboardSort {
+    #[default]
+    WinRate,
+    Wins,
+    GamesPlayed,
+    Rating,
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug, ToSchema)]
+pub struct LeaderboardEntry {
+    pub rank: u32,
+    pub player_name: String,
+    #[serde(flatten)]
+    pub record: Record,
+    pub win_rate: f64,
+    // The Glicko-2 rating, players whose games were never rated have the starting rating
+    pub rating: f64,
+}
+
+/*
+    How a finished game ended for one of its players.
+*/
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Win,
+    Loss,
+    Draw,
+}
+
+/*
+    One side of a finished game, and who played it.
+*/
+struct SeatResult {
+    color: String,
+    player_name: String,
+    winner: String,
+    reason: Option<String>,
+    opponent_is_bot: bool,
+    bot_difficulty: u8,
+}
+
+impl SeatResult {
+    fn outcome(&self) -> Outcome {
+        if self.winner == "d" {
+            Outcome::Draw
+        } else if self.winner == self.color {
+            Outcome::Win
+        } else {
+            Outcome::Loss
+        }
+    }
+}
+
+/*
+    A query of both sides of every game and who plays them, for other queries to join like a table,
+    with the columns game_id, color, player_name and is_bot. Bots, and seats still waiting for someone
+    to join, have no player_name. With of_player, only the games of the player named by the :player
+    parameter are looked up, by name.
+    Games from before online games had seats belong to the single player of the games table, who plays
+    every side that isn't a bot.
+*/
+pub fn game_sides(of_player: bool) -> String {
+    let games_of_player = if of_player {
+        "WHERE games.id IN (SELECT id FROM games WHERE player_name = :player UNION SELECT game_id FROM game_players WHERE player_name = :player)"
+    } else {
+        ""
+    };
+
+    format!(
+        "SELECT games.id AS game_id, sides.color,
+                CASE
+                    WHEN sides.is_bot THEN NULL
+                    WHEN EXISTS (SELECT 1 FROM game_players WHERE game_players.game_id = games.id)
+                        THEN (SELECT player_name FROM game_players WHERE game_players.game_id = games.id AND game_players.color = sides.color)
+                    ELSE games.player_name
+                END AS player_name,
+                sides.is_bot
+            FROM games
+            JOIN (
+                SELECT id, 'w' AS color, white_is_bot AS is_bot FROM games
+                UNION ALL SELECT id, 'b', black_is_bot FROM games
+            ) AS sides ON sides.id = games.id
+            {}",
+        games_of_player,
+    )
+}
+
+/*
+    Returns the sides of every finished game that a player played, or only the ones of the given player,
+    leaving out the games players played against themselves.
+*/
+fn load_seat_results(conn: &Connection, player_name: Option<&str>) -> rusqlite::Result<Vec<SeatResult>> {
+    let sides = game_sides(player_name.is_some());
+    let player_filter = if player_name.is_some() { "AND sides.player_name = :player" } else { "" };
+
+    let mut statement = conn.prepare(&format!(
+        "SELECT sides.color, sides.player_name, games.winner, game_results.reason,
+                opponents.is_bot AS opponent_is_bot, games.bot_difficulty
+            FROM ({sides}) AS sides
+            JOIN ({sides}) AS opponents ON opponents.game_id = sides.game_id AND opponents.color != sides.color
+            JOIN games ON games.id = sides.game_id
+            LEFT JOIN game_results ON game_results.game_id = games.id
+            WHERE games.winner != 'x'
+                AND sides.player_name IS NOT NULL
+                AND opponents.player_name IS NOT sides.player_name
+                {player_filter}"
+    ))?;
+
+    let seat_result = |row: &rusqlite::Row| Ok(SeatResult {
+        color: row.get(0)?,
+        player_name: row.get(1)?,
+        winner: row.get(2)?,
+        reason: row.get(3)?,
+        opponent_is_bot: row.get("opponent_is_bot")?,
+        bot_difficulty: row.get("bot_difficulty")?,
+    });
+
+    let seats = match player_name {
+        Some(player_name) => statement.query_map(rusqlite::named_params! { ":player": player_name }, seat_result)?,
+        None => statement.query_map([], seat_result)?,
+    };
+
+    seats.collect()
+}
+
+fn stats_from_seats<'a>(player_name: &str, seats: impl Iterator<Item = &'a SeatResult>) -> PlayerStats {
+    let mut stats = PlayerStats {
+        player_name: player_name.to_string(),
+        overall: Record::default(),
+        as_white: Record::default(),
+        as_black: Record::default(),
+        wins_by_reason: BTreeMap::new(),
+        against_humans: Record::default(),
+        against_bots: Vec::new(),
+    };
+    let mut against_bots: BTreeMap<u8, Record> = BTreeMap::new();
+
+    for seat in seats {
+        let outcome = seat.outcome();
+
+        stats.overall.add(outcome);
+
+        if seat.color == "w" {
+            stats.as_white.add(outcome);
+        } else {
+            stats.as_black.add(outcome);
+        }
+
+        if outcome == Outcome::Win {
+            let reason = seat.reason.clone().unwrap_or_else(|| "unknown".to_string());
+            *stats.wins_by_reason.entry(reason).or_default() += 1;
+        }
+
+        if seat.opponent_is_bot {
+            against_bots.entry(seat.bot_difficulty).or_default().add(outcome);
+        } else {
+            stats.against_humans.add(outcome);
+        }
+    }
+
+    stats.against_bots = against_bots.into_iter()
+        .map(|(bot_difficulty, record)| BotRecord { bot_difficulty, record })
+        .collect();
+
+    stats
+}
+
+pub fn player_stats(conn: &Connection, player_name: &str) -> rusqlite::Result<PlayerStats> {
+    let seats = load_seat_results(conn, Some(player_name))?;
+
+    Ok(stats_from_seats(player_name, seats.iter()))
+}
+
+/*
+    Ranks the players with at least min_games finished games. Ties are broken by the number of games played,
+    then by name.
+*/
+pub fn leaderboard(conn: &Connection, sort: LeaderboardSort, min_games: u32, limit: u32) -> rusqlite::Result<Vec<LeaderboardEntry>> {
+    let mut records: HashMap<String, Record> = HashMap::new();
+
+    for seat in load_seat_results(conn, None)? {
+        records.entry(seat.player_name.clone()).or_default().add(seat.outcome());
+    }
+
+    let mut statement = conn.prepare("SELECT player_name, rating FROM ratings")?;
+    let ratings = statement.query_map([], |row| Ok((row.get::<usize, String>(0)?, row.get::<usize, f64>(1)?)))?
+        .collect::<rusqlite::Result<HashMap<String, f64>>>()?;
+    let rating_of = |player_name: &str| ratings.get(player_name).copied().unwrap_or(Rating::default().rating);
+
+    let mut ranked: Vec<(String, Record)> = records.into_iter()
+        .filter(|(_, record)| record.played >= min_games)
+        .collect();
+
+    ranked.sort_by(|(name_a, a), (name_b, b)| {
+        let by_sort = match sort {
+            LeaderboardSort::Rating => rating_of(name_b).total_cmp(&rating_of(name_a)),
+            LeaderboardSort::WinRate => b.win_rate().total_cmp(&a.win_rate()),
+            LeaderboardSort::Wins => b.wins.cmp(&a.wins),
+            LeaderboardSort::GamesPlayed => b.played.cmp(&a.played),
+        };
+
+        by_sort.then(b.played.cmp(&a.played)).then(name_a.cmp(name_b))
+    });
+
+    Ok(ranked.into_iter()
+        .take(limit as usize)
+        .enumerate()
+        .map(|(index, (player_name, record))| LeaderboardEntry {
+            rank: index as u32 + 1,
+            rating: rating_of(&player_name),
+            player_name,
+            record,
+            win_rate: record.win_rate(),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::migrate, game::Game};
+
+    fn add_game(conn: &Connection, game_id: &str, bot_white: bool, bot_black: bool, seats: &[(&str, &str)], winner: &str, reason: &str) {
+        let game_state = Game::starting_position(bot_white, bot_black, 2).to_string().unwrap();
+
+        conn.execute(
+            "INSERT INTO games (id, game_state, player_name, winner, bot_difficulty, white_is_bot, black_is_bot) VALUES (?1, ?2, ?3, ?4, 2, ?5, ?6)",
+            rusqlite::params![game_id, game_state, seats[0].1, winner, bot_white, bot_black],
+        ).unwrap();
+        conn.execute(
+            "INSERT INTO game_results (game_id, reason) VALUES (?1, ?2)",
+            rusqlite::params![game_id, reason],
+        ).unwrap();
+
+        for (color, player_name) in seats {
+            conn.execute(
+                "INSERT INTO game_players (game_id, color, player_name) VALUES (?1, ?2, ?3)",
+                rusqlite::params![game_id, color, player_name],
+            ).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_player_stats() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+
+        add_game(&conn, "1", true, false, &[("b", "alice")], "b", "king_captured");
+        add_game(&conn, "2", false, true, &[("w", "alice")], "w", "king_escaped");
+        add_game(&conn, "3", false, false, &[("w", "alice"), ("b", "bob")], "b", "resignation");
+        add_game(&conn, "4", false, false, &[("w", "bob"), ("b", "alice")], "d", "draw_agreed");
+        // Games against yourself, and unfinished games don't count
+        add_game(&conn, "5", false, false, &[("w", "alice"), ("b", "alice")], "w", "edge_fort");
+        add_game(&conn, "6", false, true, &[("w", "alice")], "x", "");
+
+        let stats = player_stats(&conn, "alice").unwrap();
+        assert_eq!(stats.overall, Record { played: 4, wins: 2, losses: 1, draws: 1 });
+        assert_eq!(stats.as_white, Record { played: 2, wins: 1, losses: 1, draws: 0 });
+        assert_eq!(stats.as_black, Record { played: 2, wins: 1, losses: 0, draws: 1 });
+        assert_eq!(stats.wins_by_reason.get("king_escaped"), Some(&1));
+        assert_eq!(stats.wins_by_reason.get("king_captured"), Some(&1));
+        assert_eq!(stats.against_humans, Record { played: 2, wins: 0, losses: 1, draws: 1 });
+        assert_eq!(stats.against_bots, vec![BotRecord { bot_difficulty: 2, record: Record { played: 2, wins: 2, losses: 0, draws: 0 } }]);
+
+        assert_eq!(player_stats(&conn, "nobody").unwrap().overall, Record::default());
+    }
+
+    #[test]
+    fn test_legacy_games() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+
+        // Games from before seats only have the player_name of the games table
+        let game_state = Game::starting_position(true, false, 1).to_string().unwrap();
+        conn.execute(
+            "INSERT INTO games (id, game_state, player_name, winner, bot_difficulty, white_is_bot) VALUES ('1', ?1, 'carol', 'b', 1, 1)",
+            rusqlite::params![game_state],
+        ).unwrap();
+
+        let stats = player_stats(&conn, "carol").unwrap();
+        assert_eq!(stats.as_black, Record { played: 1, wins: 1, losses: 0, draws: 0 });
+        assert_eq!(stats.wins_by_reason.get("unknown"), Some(&1));
+    }
+
+    #[test]
+    fn test_leaderboard() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+
+        add_game(&conn, "1", false, false, &[("w", "alice"), ("b", "bob")], "w", "king_escaped");
+        add_game(&conn, "2", false, false, &[("w", "bob"), ("b", "carol")], "w", "edge_fort");
+        add_game(&conn, "3", false, false, &[("w", "carol"), ("b", "bob")], "d", "draw_agreed");
+        add_game(&conn, "4", false, false, &[("w", "bob"), ("b", "alice")], "b", "surrounded");
+
+        let by_win_rate = leaderboard(&conn, LeaderboardSort::WinRate, 1, 10).unwrap();
+        let names: Vec<&str> = by_win_rate.iter().map(|entry| entry.player_name.as_str()).collect();
+        assert_eq!(names, vec!["alice", "bob", "carol"]);
+        assert_eq!(by_win_rate[0].win_rate, 1.0);
+        assert_eq!(by_win_rate[1].rank, 2);
+        assert_eq!(by_win_rate[1].record, Record { played: 4, wins: 1, losses: 2, draws: 1 });
+
+        let by_games = leaderboard(&conn, LeaderboardSort::GamesPlayed, 3, 10).unwrap();
+        assert_eq!(by_games.len(), 1);
+        assert_eq!(by_games[0].player_name, "bob");
+
+        assert_eq!(leaderboard(&conn, LeaderboardSort::Wins, 1, 2).unwrap().len(), 2);
+
+        crate::rating::rate_game(&conn, "3", "carol", "bob", crate::game::board::Color::White, 1_000).unwrap();
+        let by_rating = leaderboard(&conn, LeaderboardSort::Rating, 1, 10).unwrap();
+        let names: Vec<&str> = by_rating.iter().map(|entry| entry.player_name.as_str()).collect();
+        assert_eq!(names, vec!["carol", "alice", "bob"]);
+        assert!(by_rating[0].rating > 1500.0);
+        assert_eq!(by_rating[1].rating, 1500.0);
+    }
+}
@@ -0,0 +1,304 @@
+use std::{
+    sync::{atomic::{AtomicU32, Ordering}, Mutex},
+    thread,
+};
+
+use crate::game::{
+    Game,
+    board::Color,
+    engine::BotPlayer,
+};
+
+// Games between bots that never make progress are drawn after this many moves
+pub const DEFAULT_MAX_PLIES: u32 = 500;
+
+// How many standard errors the error bars of the Elo difference span, for a 95% confidence interval
+const CONFIDENCE_Z: f64 = 1.96;
+
+/*
+    A match between two bots. Every opening is played twice, once with each bot on either side,
+    so that neither gets the better side of an opening more often. Without openings, every game
+    starts from the usual starting position.
+*/
+pub struct Tournament {
+    pub first: BotPlayer,
+    pub second: BotPlayer,
+    pub games: u32,
+    pub threads: usize,
+    // Positions in the format of Game::to_string
+    pub openings: Vec<String>,
+    pub max_plies: u32,
+}
+
+/*
+    The results of a match, counted for the first bot.
+*/
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Score {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+/*
+    The outcome of a sequential probability ratio test, of whether the first bot is elo1 stronger
+    than the second, against it being only elo0 stronger.
+*/
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SprtVerdict {
+    // The first bot is at least elo1 stronger
+    AcceptH1,
+    // The first bot is at most elo0 stronger
+    AcceptH0,
+    // Not enough games were played to tell
+    Continue,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    // The chance of accepting H1 while H0 is true
+    pub alpha: f64,
+    // The chance of accepting H0 while H1 is true
+    pub beta: f64,
+}
+
+impl Default for Sprt {
+    fn default() -> Sprt {
+        Sprt { elo0: 0.0, elo1: 20.0, alpha: 0.05, beta: 0.05 }
+    }
+}
+
+impl Sprt {
+    /*
+        Returns the log-likelihood ratio of the score, using the normal approximation of the
+        generalized SPRT. The test ends once it leaves the bounds.
+    */
+    pub fn llr(&self, score: &Score) -> f64 {
+        let games = f64::from(score.games());
+        let variance = score.variance();
+
+        if games == 0.0 || variance == 0.0 {
+            return 0.0;
+        }
+
+        let score0 = elo_to_score(self.elo0);
+        let score1 = elo_to_score(self.elo1);
+
+        games * (score1 - score0) * (2.0 * score.mean() - score0 - score1) / (2.0 * variance)
+    }
+
+    pub fn bounds(&self) -> (f64, f64) {
+        ((self.beta / (1.0 - self.alpha)).ln(), ((1.0 - self.beta) / self.alpha).ln())
+    }
+
+    pub fn verdict(&self, score: &Score) -> SprtVerdict {
+        let llr = self.llr(score);
+        let (lower, upper) = self.bounds();
+
+        if llr >= upper {
+            SprtVerdict::AcceptH1
+        } else if llr <= lower {
+            SprtVerdict::AcceptH0
+        } else {
+            SprtVerdict::Continue
+        }
+    }
+}
+
+impl Score {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    fn add(&mut self, result: f64) {
+        if result == 1.0 {
+            self.wins += 1;
+        } else if result == 0.0 {
+            self.losses += 1;
+        } else {
+            self.draws += 1;
+        }
+    }
+
+    /*
+        The average points per game of the first bot, a win is 1 and a draw 0.5.
+    */
+    pub fn mean(&self) -> f64 {
+        (f64::from(self.wins) + f64::from(self.draws) / 2.0) / f64::from(self.games())
+    }
+
+    /*
+        The variance of the points of a single game.
+    */
+    fn variance(&self) -> f64 {
+        let mean = self.mean();
+        let games = f64::from(self.games());
+
+        (f64::from(self.wins) * (1.0 - mean).powi(2)
+            + f64::from(self.draws) * (0.5 - mean).powi(2)
+            + f64::from(self.losses) * mean.powi(2)) / games
+    }
+
+    /*
+        Returns how many Elo points the first bot is stronger than the second, and the error
+        margin of that at 95% confidence. None until both bots have scored something, the
+        difference is infinite before that.
+    */
+    pub fn elo_difference(&self) -> Option<(f64, f64)> {
+        let mean = self.mean();
+        if !(mean > 0.0 && mean < 1.0) {
+            return None;
+        }
+
+        let error = CONFIDENCE_Z * (self.variance() / f64::from(self.games())).sqrt();
+        let lower = score_to_elo((mean - error).max(f64::EPSILON));
+        let upper = score_to_elo((mean + error).min(1.0 - f64::EPSILON));
+
+        Some((score_to_elo(mean), (upper - lower) / 2.0))
+    }
+}
+
+fn elo_to_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+fn score_to_elo(score: f64) -> f64 {
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+/*
+    Plays a game between the bots from the opening, or from the starting position without one,
+    and returns the points of White.
+*/
+pub fn play_game(white: BotPlayer, black: BotPlayer, opening: Option<&str>, max_plies: u32) -> Result<f64, String> {
+    let mut game = match opening {
+        Some(opening) => Game::from_string(opening.to_string(), 1)?,
+        None => Game::starting_position(true, true, 1),
+    };
+    game.bot_white = true;
+    game.bot_black = true;
+    game.white_engine = Some(white);
+    game.black_engine = Some(black);
+
+    while !game.is_over() {
+        if game.ply >= max_plies {
+            game.draw_by_move_limit();
+            break;
+        }
+
+        game.make_bot_move(None)?;
+    }
+
+    Ok(match game.get_winner() {
+        Color::White => 1.0,
+        Color::Black => 0.0,
+        Color::None => 0.5,
+    })
+}
+
+/*
+    Plays the games of the tournament on its threads, and calls on_game after each with the score so far.
+*/
+pub fn run_tournament(tournament: &Tournament, on_game: impl Fn(&Score) + Sync) -> Result<Score, String> {
+    let next_game = AtomicU32::new(0);
+    let score = Mutex::new(Score::default());
+    let failure = Mutex::new(None);
+
+    thread::scope(|scope| {
+        for _ in 0..tournament.threads.max(1) {
+            scope.spawn(|| loop {
+                let game = next_game.fetch_add(1, Ordering::Relaxed);
+                if game >= tournament.games || failure.lock().unwrap().is_some() {
+                    break;
+                }
+
+                // The two games of an opening follow each other, with the bots swapping sides
+                let opening = match tournament.openings.len() {
+                    0 => None,
+                    len => Some(tournament.openings[(game / 2) as usize % len].as_str()),
+                };
+                let first_is_white = game.is_multiple_of(2);
+                let (white, black) = if first_is_white {
+                    (tournament.first, tournament.second)
+                } else {
+                    (tournament.second, tournament.first)
+                };
+
+                match play_game(white, black, opening, tournament.max_plies) {
+                    Ok(white_points) => {
+                        let mut score = score.lock().unwrap();
+                        score.add(if first_is_white { white_points } else { 1.0 - white_points });
+                        on_game(&score);
+                    },
+                    Err(err) => {
+                        *failure.lock().unwrap() = Some(err);
+                        break;
+                    },
+                }
+            });
+        }
+    });
+
+    match failure.into_inner().unwrap() {
+        Some(err) => Err(err),
+        None => Ok(score.into_inner().unwrap()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::engine::Engine;
+
+    #[test]
+    fn test_elo_difference() {
+        let even = Score { wins: 10, draws: 10, losses: 10 };
+        let (elo, margin) = even.elo_difference().unwrap();
+        assert!(elo.abs() < 1e-9);
+        assert!(margin > 0.0);
+
+        // 75% is about 191 Elo
+        let (elo, _) = Score { wins: 75, draws: 0, losses: 25 }.elo_difference().unwrap();
+        assert!((elo - 190.85).abs() < 0.01);
+
+        // More games, smaller error bars
+        let (_, margin_more_games) = Score { wins: 100, draws: 100, losses: 100 }.elo_difference().unwrap();
+        assert!(margin_more_games < margin);
+
+        assert_eq!(Score { wins: 5, draws: 0, losses: 0 }.elo_difference(), None);
+    }
+
+    #[test]
+    fn test_sprt() {
+        let sprt = Sprt::default();
+        let (lower, upper) = sprt.bounds();
+        assert!((upper - 2.944).abs() < 0.001);
+        assert!((lower + 2.944).abs() < 0.001);
+
+        assert_eq!(sprt.verdict(&Score { wins: 300, draws: 100, losses: 100 }), SprtVerdict::AcceptH1);
+        assert_eq!(sprt.verdict(&Score { wins: 100, draws: 100, losses: 300 }), SprtVerdict::AcceptH0);
+        assert_eq!(sprt.verdict(&Score { wins: 3, draws: 2, losses: 3 }), SprtVerdict::Continue);
+        assert_eq!(sprt.verdict(&Score::default()), SprtVerdict::Continue);
+    }
+
+    #[test]
+    fn test_run_tournament() {
+        let random = BotPlayer { engine: Engine::Random, difficulty: 1 };
+        let opening = Game::starting_position(false, false, 1).to_string().unwrap();
+        let tournament = Tournament {
+            first: random,
+            second: random,
+            games: 4,
+            threads: 2,
+            openings: vec![opening],
+            max_plies: 40,
+        };
+
+        let played = AtomicU32::new(0);
+        let score = run_tournament(&tournament, |_| { played.fetch_add(1, Ordering::Relaxed); }).unwrap();
+        assert_eq!(score.games(), 4);
+        assert_eq!(played.into_inner(), 4);
+    }
+}